@@ -0,0 +1,76 @@
+use crate::location::Location;
+use serde::Deserialize;
+
+/// What the picker does when one of its keys is pressed
+#[derive(PartialEq, Eq, Hash, Debug, Deserialize, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Session,
+    Directory,
+    DirectoryTree,
+    Repository,
+    Machine,
+    Everywhere,
+    ToggleGroup,
+    ToggleSort,
+    ToggleRelativeTime,
+    CycleExitStatus,
+    CycleTimeRange,
+    CycleLocation,
+    Delete,
+    Copy,
+}
+
+/// Every action with the name of its setting and its default key
+pub const ACTIONS: [(Action, &str, &str); 14] = [
+    (Action::Session, "session", "f1"),
+    (Action::Directory, "directory", "f2"),
+    (Action::Machine, "machine", "f3"),
+    (Action::Everywhere, "everywhere", "f4"),
+    (Action::ToggleGroup, "toggle_group", "f5"),
+    (Action::DirectoryTree, "directory_tree", "f6"),
+    (Action::Repository, "repository", "f7"),
+    (Action::ToggleSort, "toggle_sort", "ctrl-s"),
+    (Action::ToggleRelativeTime, "toggle_relative_time", "alt-r"),
+    (Action::CycleExitStatus, "cycle_exit_status", "ctrl-o"),
+    (Action::CycleTimeRange, "cycle_time_range", "ctrl-t"),
+    (Action::CycleLocation, "cycle_location", "ctrl-r"),
+    (Action::Delete, "delete", "ctrl-x"),
+    (Action::Copy, "copy", "alt-c"),
+];
+
+impl Action {
+    /// Name of the action in the `[keys]` section of the configuration
+    pub fn name(self) -> &'static str {
+        ACTIONS.iter().find(|(a, _, _)| *a == self).unwrap().1
+    }
+
+    pub fn default_key(self) -> &'static str {
+        ACTIONS.iter().find(|(a, _, _)| *a == self).unwrap().2
+    }
+
+    /// The action switching to the location
+    pub fn for_location(location: Location) -> Action {
+        match location {
+            Location::Session => Action::Session,
+            Location::Directory => Action::Directory,
+            Location::DirectoryTree => Action::DirectoryTree,
+            Location::Repository => Action::Repository,
+            Location::Machine => Action::Machine,
+            Location::Everywhere => Action::Everywhere,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_match_settings() {
+        for (action, name, _) in ACTIONS {
+            let parsed: Action = serde_json::from_str(&format!("\"{}\"", name)).unwrap();
+            assert_eq!(parsed, action);
+        }
+    }
+}
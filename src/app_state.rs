@@ -0,0 +1,34 @@
+use crate::environment::*;
+use crate::exit_filter::ExitFilter;
+use crate::sort::SortMode;
+use crate::time_range::TimeRange;
+
+/// The environment the picker was started in and the settings used to scope the queries
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AppState {
+    pub session: i64,
+    pub dir: String,
+    pub machine: String,
+    pub repository: Option<String>,
+    pub sort: SortMode,
+    pub exit_filter: ExitFilter,
+    pub time_range: TimeRange,
+    /// The range given on the command line, ctrl-t cycles back to it
+    pub custom_time_range: Option<TimeRange>,
+}
+
+impl AppState {
+    pub fn from_environment() -> Self {
+        let dir = get_current_dir();
+        AppState {
+            session: get_current_session_id().parse::<i64>().unwrap_or(-1),
+            repository: get_repository_root(&dir),
+            dir,
+            machine: get_current_host(),
+            sort: SortMode::default(),
+            exit_filter: ExitFilter::default(),
+            time_range: TimeRange::default(),
+            custom_time_range: None,
+        }
+    }
+}
@@ -0,0 +1,16 @@
+use serde::Deserialize;
+
+/// Information shown in front of the command in the list
+#[derive(PartialEq, Eq, Hash, Debug, Deserialize, Copy, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub enum Column {
+    /// Start time or age of the command
+    Date,
+    /// ✓ or ✗ for the exit status
+    ExitStatus,
+    /// Runtime, e.g. `1m30s`
+    Duration,
+}
+
+/// The columns shown if none are configured
+pub const DEFAULT_COLUMNS: [Column; 1] = [Column::Date];
@@ -0,0 +1,355 @@
+use crate::action::{Action, ACTIONS};
+use crate::column::Column;
+use crate::date_format::{validate_pattern, DateFormat};
+use crate::join::Join;
+use crate::location::Location;
+use crate::sort::SortMode;
+use crossterm::event::KeyEvent;
+use serde::Deserialize;
+use skim::binds::parse_key;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Keys skim or the picker already use for something else
+const RESERVED_KEYS: [&str; 8] = [
+    "enter", "esc", "tab", "btab", "ctrl-c", "ctrl-d", "ctrl-u", "ctrl-z",
+];
+
+/// Settings read from `config.toml`, unset values fall back to the built-in defaults
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub database: Option<Databases>,
+    pub location: Option<Location>,
+    pub group: Option<bool>,
+    pub date_format: Option<String>,
+    pub time_format: Option<String>,
+    pub relative_time: Option<bool>,
+    pub columns: Option<Vec<Column>>,
+    pub sort: Option<SortMode>,
+    pub nosort: Option<bool>,
+    pub preview: Option<String>,
+    pub preview_neighbours: Option<usize>,
+    pub color: Option<String>,
+    pub multi: Option<bool>,
+    pub join: Option<Join>,
+    pub keys: KeyBindings,
+}
+
+/// One database or a list of them, the first one is the main database
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Databases {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Databases {
+    pub fn paths(&self) -> Vec<String> {
+        match self {
+            Databases::One(path) => vec![path.clone()],
+            Databases::Many(paths) => paths.clone(),
+        }
+    }
+
+    fn paths_mut(&mut self) -> Vec<&mut String> {
+        match self {
+            Databases::One(path) => vec![path],
+            Databases::Many(paths) => paths.iter_mut().collect(),
+        }
+    }
+}
+
+/// Keys of the picker actions differing from the defaults, in the syntax of skim's `--bind`
+#[derive(Debug, Default, Deserialize)]
+#[serde(transparent)]
+pub struct KeyBindings {
+    keys: HashMap<Action, String>,
+}
+
+impl KeyBindings {
+    /// The key bound to the action
+    pub fn key(&self, action: Action) -> &str {
+        self.keys
+            .get(&action)
+            .map(String::as_str)
+            .unwrap_or(action.default_key())
+    }
+
+    /// Every action with its key
+    pub fn all(&self) -> Vec<(Action, &str)> {
+        ACTIONS
+            .iter()
+            .map(|(action, _, _)| (*action, self.key(*action)))
+            .collect()
+    }
+
+    /// The action bound to the key event
+    pub fn action(&self, key: &KeyEvent) -> Option<Action> {
+        self.all()
+            .into_iter()
+            .find(|(_, binding)| is_key(binding, key))
+            .map(|(action, _)| action)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let mut used: Vec<(Action, KeyEvent)> = Vec::new();
+        for (action, binding) in self.all() {
+            let name = action.name();
+            let key = parse_key(binding)
+                .map_err(|e| format!("keys.{}: invalid key `{}`: {}", name, binding, e))?;
+            if RESERVED_KEYS.iter().any(|reserved| is_key(reserved, &key)) {
+                return Err(format!("keys.{}: `{}` is reserved", name, binding));
+            }
+            if let Some((other, _)) = used.iter().find(|(_, k)| same_key(k, &key)) {
+                return Err(format!(
+                    "keys.{}: `{}` is already used by keys.{}",
+                    name,
+                    binding,
+                    other.name()
+                ));
+            }
+            used.push((action, key));
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config: Config = toml::from_str(text).map_err(|e| e.to_string())?;
+        if let (Some(databases), Ok(home)) = (&mut config.database, env::var("HOME")) {
+            for database in databases.paths_mut() {
+                if let Some(path) = database.strip_prefix("~/") {
+                    *database = format!("{}/{}", home, path);
+                }
+            }
+        }
+        config.keys.validate()?;
+        if let Some(date_format) = &config.date_format {
+            DateFormat::parse(date_format, "").map_err(|e| format!("date_format: {}", e))?;
+        }
+        if let Some(columns) = &config.columns {
+            for (i, column) in columns.iter().enumerate() {
+                if columns[..i].contains(column) {
+                    return Err(format!("columns: {:?} is listed twice", column));
+                }
+            }
+        }
+        if let Some(time_format) = &config.time_format {
+            validate_pattern(time_format).map_err(|e| format!("time_format: {}", e))?;
+        }
+        if let Some(preview) = &config.preview {
+            validate_preview(preview)?;
+        }
+        if let Some(color) = &config.color {
+            validate_color(color)?;
+        }
+        Ok(config)
+    }
+}
+
+/// Get the configuration, the built-in defaults if none was loaded
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+/// Read and validate the configuration file, if there is one
+pub fn load_config() -> Result<(), String> {
+    let config = match config_path() {
+        Some(path) if path.exists() => {
+            let text = fs::read_to_string(&path)
+                .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+            Config::parse(&text).map_err(|e| format!("Invalid {}: {}", path.display(), e))?
+        }
+        _ => Config::default(),
+    };
+    let _ = CONFIG.set(config);
+    Ok(())
+}
+
+/// `$XDG_CONFIG_HOME/zsh-histdb-skim/config.toml`, with `~/.config` as fallback
+fn config_path() -> Option<PathBuf> {
+    let config_home = env::var("XDG_CONFIG_HOME")
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var("HOME")
+                .ok()
+                .map(|home| PathBuf::from(home).join(".config"))
+        })?;
+    Some(config_home.join("zsh-histdb-skim").join("config.toml"))
+}
+
+/// Whether the key event is the one of the binding
+fn is_key(binding: &str, key: &KeyEvent) -> bool {
+    parse_key(binding)
+        .map(|bound| same_key(&bound, key))
+        .unwrap_or(false)
+}
+
+fn same_key(a: &KeyEvent, b: &KeyEvent) -> bool {
+    a.code == b.code && a.modifiers == b.modifiers
+}
+
+/// Check the preview window layout, e.g. `right:50%` or `down:10:wrap`
+fn validate_preview(preview: &str) -> Result<(), String> {
+    for part in preview.split(':').filter(|part| !part.is_empty()) {
+        let valid = matches!(
+            part,
+            "up" | "down" | "left" | "right" | "hidden" | "nohidden" | "wrap" | "nowrap"
+        ) || part.starts_with('+')
+            || part.trim_end_matches('%').parse::<u16>().is_ok();
+        if !valid {
+            return Err(format!("preview: unknown layout `{}`", part));
+        }
+    }
+    Ok(())
+}
+
+/// Check the skim color specification, e.g. `dark,matched:108,header:#ff8700`
+fn validate_color(color: &str) -> Result<(), String> {
+    for part in color.split(',') {
+        match part.split_once(':') {
+            Some((_, spec)) => {
+                let value = spec.split(':').next().unwrap_or_default();
+                let valid = value == "-1"
+                    || value.parse::<u8>().is_ok()
+                    || (value.len() == 7
+                        && value.starts_with('#')
+                        && u32::from_str_radix(&value[1..], 16).is_ok());
+                if !valid {
+                    return Err(format!("color: invalid color `{}` in `{}`", value, part));
+                }
+            }
+            None => {
+                if !matches!(
+                    part.replace('_', "-").as_str(),
+                    "dark"
+                        | "default"
+                        | "light"
+                        | "molokai"
+                        | "16"
+                        | "bw"
+                        | "none"
+                        | "empty"
+                        | "catppuccin-mocha"
+                        | "catppuccin-macchiato"
+                        | "catppuccin-latte"
+                        | "catppuccin-frappe"
+                ) {
+                    return Err(format!("color: unknown theme `{}`", part));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_empty_config() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(config.location, None);
+        assert_eq!(config.keys.key(Action::Session), "f1");
+    }
+
+    #[test]
+    fn parses_settings() {
+        let config = Config::parse(
+            r#"
+            location = "directory-tree"
+            group = false
+            date_format = "us"
+            time_format = "%I:%M %p"
+            sort = "frecency"
+            multi = true
+            join = "and"
+            preview = "down:40%"
+            color = "light,matched:108"
+            columns = ["exit-status", "duration", "date"]
+
+            [keys]
+            session = "alt-s"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.location, Some(Location::DirectoryTree));
+        assert_eq!(config.group, Some(false));
+        assert_eq!(config.date_format.as_deref(), Some("us"));
+        assert_eq!(config.time_format.as_deref(), Some("%I:%M %p"));
+        assert_eq!(config.sort, Some(SortMode::Frecency));
+        assert_eq!(config.multi, Some(true));
+        assert_eq!(config.join, Some(Join::And));
+        assert_eq!(
+            config.columns,
+            Some(vec![Column::ExitStatus, Column::Duration, Column::Date])
+        );
+        assert_eq!(config.keys.key(Action::Session), "alt-s");
+        assert_eq!(config.keys.key(Action::Directory), "f2");
+    }
+
+    #[test]
+    fn parses_database_lists() {
+        let config = Config::parse("database = \"/a.db\"").unwrap();
+        assert_eq!(config.database.unwrap().paths(), vec!["/a.db"]);
+        let config = Config::parse("database = [\"/a.db\", \"/b.db\"]").unwrap();
+        assert_eq!(config.database.unwrap().paths(), vec!["/a.db", "/b.db"]);
+    }
+
+    #[test]
+    fn rejects_unknown_settings() {
+        let error = Config::parse("colour = \"dark\"").unwrap_err();
+        assert!(error.contains("unknown field `colour`"), "{}", error);
+        assert!(Config::parse("location = \"nowhere\"").is_err());
+        assert!(Config::parse("group = \"yes\"").is_err());
+        let error = Config::parse("columns = [\"date\", \"date\"]").unwrap_err();
+        assert_eq!(error, "columns: Date is listed twice");
+        let error = Config::parse("date_format = \"%Y-%Q\"").unwrap_err();
+        assert_eq!(error, "date_format: invalid strftime pattern `%Y-%Q`");
+    }
+
+    #[test]
+    fn rejects_invalid_keys() {
+        let error = Config::parse("[keys]\nsession = \"hyper-x\"").unwrap_err();
+        assert!(error.starts_with("keys.session: invalid key"), "{}", error);
+        let error = Config::parse("[keys]\nsession = \"f2\"").unwrap_err();
+        assert_eq!(
+            error,
+            "keys.directory: `f2` is already used by keys.session"
+        );
+        let error = Config::parse("[keys]\ndelete = \"enter\"").unwrap_err();
+        assert_eq!(error, "keys.delete: `enter` is reserved");
+        let error = Config::parse("[keys]\nsesion = \"f9\"").unwrap_err();
+        assert!(error.contains("unknown variant `sesion`"), "{}", error);
+    }
+
+    #[test]
+    fn finds_remapped_actions() {
+        let config = Config::parse("[keys]\nsession = \"alt-1\"\ntoggle_group = \"f1\"").unwrap();
+        let key = |binding| parse_key(binding).unwrap();
+        assert_eq!(config.keys.action(&key("f1")), Some(Action::ToggleGroup));
+        assert_eq!(config.keys.action(&key("alt-1")), Some(Action::Session));
+        assert_eq!(config.keys.action(&key("f5")), None);
+        assert_eq!(
+            config.keys.action(&key("ctrl-r")),
+            Some(Action::CycleLocation)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_layout_and_colors() {
+        assert!(Config::parse("preview = \"sideways\"").is_err());
+        assert!(Config::parse("color = \"dark,matched:orange\"").is_err());
+        assert!(Config::parse("color = \"solarized\"").is_err());
+    }
+}
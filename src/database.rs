@@ -0,0 +1,626 @@
+use crate::environment::*;
+use crate::history::History;
+use crate::sort::SortMode;
+use rusqlite::{params, Connection, OpenFlags, Transaction};
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use std::path::Path;
+use std::sync::mpsc::{self, IntoIter, SyncSender};
+use std::thread;
+
+/// Entries read ahead per database while the others are behind
+const READ_AHEAD: usize = 100;
+
+/// The entries of several databases, interleaved in the order of the sort mode. Errors
+/// are passed on as soon as they are read
+pub struct Interleaved {
+    sources: Vec<Peekable<IntoIter<rusqlite::Result<History>>>>,
+    sort: SortMode,
+}
+
+impl Iterator for Interleaved {
+    type Item = rusqlite::Result<History>;
+
+    fn next(&mut self) -> Option<rusqlite::Result<History>> {
+        let sort = self.sort;
+        let mut next: Option<(usize, &History)> = None;
+        for (i, source) in self.sources.iter_mut().enumerate() {
+            match source.peek() {
+                Some(Ok(entry))
+                    if next.is_none_or(|(_, first)| comes_before(entry, first, sort)) =>
+                {
+                    next = Some((i, entry));
+                }
+                Some(Err(_)) => return source.next(),
+                _ => (),
+            }
+        }
+        let i = next.map(|(i, _)| i)?;
+        self.sources[i].next()
+    }
+}
+
+/// Whether the entry is listed before the other one
+fn comes_before(entry: &History, other: &History, sort: SortMode) -> bool {
+    let by_start = entry.start.cmp(&other.start).reverse();
+    let order = match sort {
+        SortMode::Recent => by_start,
+        SortMode::Frecency => other
+            .frecency
+            .partial_cmp(&entry.frecency)
+            .unwrap_or(Ordering::Equal)
+            .then(by_start),
+    };
+    order == Ordering::Less
+}
+
+/// Run the query on every database in parallel, the entries are read while they are consumed.
+/// Only the main database has to be readable. Grouped queries group each database on its own,
+/// a command found in several of them comes once per database with the counts of that one
+pub fn read_history(query: &str, sort: SortMode) -> rusqlite::Result<Interleaved> {
+    let mut sources = Vec::new();
+    for (source, path) in get_histdb_databases().into_iter().enumerate() {
+        let opened =
+            Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY).and_then(|conn| {
+                conn.prepare(query)?;
+                Ok(conn)
+            });
+        let conn = match opened {
+            Ok(conn) => conn,
+            // copies synced from other hosts may be missing for a while
+            Err(_) if source > 0 => continue,
+            Err(e) => return Err(e),
+        };
+        let (tx, rx) = mpsc::sync_channel(READ_AHEAD);
+        let query = query.to_string();
+        thread::spawn(move || {
+            if let Err(e) = send_history(&conn, &query, source, &tx) {
+                if source == 0 {
+                    let _ = tx.send(Err(e));
+                }
+            }
+        });
+        sources.push(rx.into_iter().peekable());
+    }
+    Ok(Interleaved { sources, sort })
+}
+
+/// Send the entries of the query until the receiver is gone. Entries of the other databases
+/// that can't be read are skipped, like the databases that can't be opened
+fn send_history(
+    conn: &Connection,
+    query: &str,
+    source: usize,
+    tx: &SyncSender<rusqlite::Result<History>>,
+) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare(query)?;
+    for entry in stmt.query_map([], History::from_row)? {
+        let entry = match entry {
+            Ok(mut entry) => {
+                entry.source = source;
+                Ok(entry)
+            }
+            Err(_) if source > 0 => continue,
+            Err(e) => Err(e),
+        };
+        if tx.send(entry).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// A command run in the same session as another one
+#[derive(Clone, Debug)]
+pub struct Neighbour {
+    pub cmd: String,
+    pub start: u64,
+}
+
+/// Read up to `count` commands run before and after the entry in its session, in execution order
+pub fn read_neighbours(
+    source: usize,
+    id: i64,
+    session: i64,
+    host: &str,
+    start: u64,
+    count: usize,
+) -> rusqlite::Result<(Vec<Neighbour>, Vec<Neighbour>)> {
+    let path = get_histdb_databases()
+        .into_iter()
+        .nth(source)
+        .unwrap_or_default();
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    session_neighbours(&conn, id, session, host, start, count)
+}
+
+/// The neighbours of the entry, read with the connection of its database
+fn session_neighbours(
+    conn: &Connection,
+    id: i64,
+    session: i64,
+    host: &str,
+    start: u64,
+    count: usize,
+) -> rusqlite::Result<(Vec<Neighbour>, Vec<Neighbour>)> {
+    let query = |comparison: &str, order: &str| {
+        format!(
+            "select commands.argv as cmd, history.start_time as start
+            from history
+                left join commands on history.command_id = commands.id
+                left join places on history.place_id = places.id
+            where history.session == ?1 and places.host == ?2
+                and (history.start_time, history.id) {} (?3, ?4)
+            order by history.start_time {}, history.id {}
+            limit ?5",
+            comparison, order, order
+        )
+    };
+    let read = |query: String| -> rusqlite::Result<Vec<Neighbour>> {
+        let mut stmt = conn.prepare(&query)?;
+        let rows = stmt.query_map(
+            params![session, host, start as i64, id, count as i64],
+            |row| {
+                Ok(Neighbour {
+                    cmd: row.get("cmd")?,
+                    start: row.get::<_, i64>("start")? as u64,
+                })
+            },
+        )?;
+        rows.collect()
+    };
+    let mut before = read(query("<", "desc"))?;
+    before.reverse();
+    let after = read(query(">", "asc"))?;
+    Ok((before, after))
+}
+
+/// The path of the main database, an error if none is set. An empty path would make
+/// sqlite open a temporary database and the changes would be lost
+fn main_database(path: String) -> rusqlite::Result<String> {
+    if path.is_empty() {
+        return Err(rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CANTOPEN),
+            Some("No database, set HISTDB_FILE or `database`".to_string()),
+        ));
+    }
+    Ok(path)
+}
+
+/// Open the histdb database for changes
+pub fn open_writable() -> rusqlite::Result<Connection> {
+    Connection::open_with_flags(
+        main_database(get_histdb_database())?,
+        OpenFlags::SQLITE_OPEN_READ_WRITE,
+    )
+}
+
+/// Delete a single execution of a command
+pub fn delete_entry(conn: &mut Connection, id: i64) -> rusqlite::Result<usize> {
+    let tx = conn.transaction()?;
+    let deleted = tx.execute("delete from history where id = ?1", params![id])?;
+    remove_unreferenced(&tx)?;
+    tx.commit()?;
+    Ok(deleted)
+}
+
+/// Delete all executions of a command, everywhere
+pub fn delete_command(conn: &mut Connection, cmd: &str) -> rusqlite::Result<usize> {
+    let tx = conn.transaction()?;
+    let deleted = tx.execute(
+        "delete from history where command_id in (select id from commands where argv = ?1)",
+        params![cmd],
+    )?;
+    remove_unreferenced(&tx)?;
+    tx.commit()?;
+    Ok(deleted)
+}
+
+/// Remove commands and places no history entry refers to anymore
+fn remove_unreferenced(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "delete from commands where id not in (select distinct command_id from history where command_id is not null)",
+        [],
+    )?;
+    tx.execute(
+        "delete from places where id not in (select distinct place_id from history where place_id is not null)",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Open the histdb database for an import, creating it with the histdb schema if needed
+pub fn open_for_import(path: String) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(main_database(path)?)?;
+    create_schema(&conn)?;
+    Ok(conn)
+}
+
+/// Open the histdb database without changing it, none if the file or its tables don't exist yet
+pub fn open_existing(path: String) -> rusqlite::Result<Option<Connection>> {
+    let path = main_database(path)?;
+    if !Path::new(&path).exists() {
+        return Ok(None);
+    }
+    let conn = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let tables: i64 = conn.query_row(
+        "select count(*) from sqlite_master
+        where type = 'table' and name in ('commands', 'places', 'history')",
+        [],
+        |row| row.get(0),
+    )?;
+    Ok(if tables == 3 { Some(conn) } else { None })
+}
+
+/// Create the tables and indices of histdb that are missing
+pub fn create_schema(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "create table if not exists commands (id integer primary key autoincrement, argv text, unique(argv) on conflict ignore);
+        create table if not exists places (id integer primary key autoincrement, host text, dir text, unique(host, dir) on conflict ignore);
+        create table if not exists history (id integer primary key autoincrement,
+            session int,
+            command_id int references commands (id),
+            place_id int references places (id),
+            exit_status int,
+            start_time int,
+            duration int);
+        create index if not exists hist_time on history(start_time);
+        create index if not exists place_dir on places(dir);
+        create index if not exists place_host on places(host);
+        create index if not exists history_command_place on history(command_id, place_id);",
+    )
+}
+
+/// A session number no entry uses yet
+pub fn unused_session(conn: &Connection) -> rusqlite::Result<i64> {
+    conn.query_row(
+        "select coalesce(max(session), 0) + 1 from history",
+        [],
+        |row| row.get(0),
+    )
+}
+
+/// Whether the command already ran on the host, at the start time if one is given
+pub fn entry_exists(
+    conn: &Connection,
+    cmd: &str,
+    host: &str,
+    start: Option<i64>,
+) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "select exists(select 1 from history
+            join commands on history.command_id = commands.id
+            join places on history.place_id = places.id
+        where commands.argv = ?1 and places.host = ?2
+            and (?3 is null or history.start_time = ?3))",
+        params![cmd, host, start],
+        |row| row.get(0),
+    )
+}
+
+/// Insert an execution of a command
+pub fn insert_entry(
+    tx: &Transaction,
+    cmd: &str,
+    host: &str,
+    dir: &str,
+    session: i64,
+    start: i64,
+    duration: Option<i64>,
+) -> rusqlite::Result<()> {
+    tx.execute("insert into commands (argv) values (?1)", params![cmd])?;
+    tx.execute(
+        "insert into places (host, dir) values (?1, ?2)",
+        params![host, dir],
+    )?;
+    tx.execute(
+        "insert into history (session, command_id, place_id, start_time, duration)
+        select ?1, commands.id, places.id, ?2, ?3
+        from commands, places
+        where commands.argv = ?4 and places.host = ?5 and places.dir = ?6",
+        params![session, start, duration, cmd, host, dir],
+    )?;
+    Ok(())
+}
+
+/// What merging another database added
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct MergeSummary {
+    pub commands: usize,
+    pub places: usize,
+    pub entries: usize,
+    pub duplicates: usize,
+}
+
+/// Copy the history of another histdb database into this one. Commands and places get the
+/// ids of this database, entries with the same host, session, start time and command as one
+/// already here are skipped
+pub fn merge_database(conn: &mut Connection, other: &str) -> rusqlite::Result<MergeSummary> {
+    conn.execute("attach database ?1 as other", params![other])?;
+    let result = merge_attached(conn);
+    conn.execute("detach database other", [])?;
+    result
+}
+
+fn merge_attached(conn: &mut Connection) -> rusqlite::Result<MergeSummary> {
+    let tx = conn.transaction()?;
+    let total: i64 = tx.query_row("select count(*) from other.history", [], |row| row.get(0))?;
+    let commands = tx.execute(
+        "insert into commands (argv) select argv from other.commands",
+        [],
+    )?;
+    let places = tx.execute(
+        "insert into places (host, dir) select host, dir from other.places",
+        [],
+    )?;
+    let entries = tx.execute(
+        "insert into history (session, command_id, place_id, exit_status, start_time, duration)
+        select theirs.session, commands.id, places.id, theirs.exit_status, theirs.start_time, theirs.duration
+        from other.history as theirs
+            join other.commands as their_commands on theirs.command_id = their_commands.id
+            join other.places as their_places on theirs.place_id = their_places.id
+            join commands on commands.argv = their_commands.argv
+            join places on places.host = their_places.host and places.dir = their_places.dir
+        where not exists (
+            select 1 from history
+                join places as our_places on history.place_id = our_places.id
+            where our_places.host = their_places.host
+                and history.session = theirs.session
+                and history.start_time = theirs.start_time
+                and history.command_id = commands.id)
+        group by their_places.host, theirs.session, theirs.start_time, commands.id",
+        [],
+    )?;
+    tx.commit()?;
+    Ok(MergeSummary {
+        commands,
+        places,
+        entries,
+        duplicates: total as usize - entries,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(start: u64, frecency: Option<f64>, source: usize) -> History {
+        let mut entry = History::example(1, "ls", start);
+        entry.frecency = frecency;
+        entry.source = source;
+        entry
+    }
+
+    /// Interleave the entries of the sources, as source and start of each entry
+    fn interleave(sources: Vec<Vec<History>>, sort: SortMode) -> Vec<(usize, u64)> {
+        let sources = sources
+            .into_iter()
+            .map(|entries| {
+                let (tx, rx) = mpsc::sync_channel(entries.len());
+                for entry in entries {
+                    tx.send(Ok(entry)).unwrap();
+                }
+                rx.into_iter().peekable()
+            })
+            .collect();
+        Interleaved { sources, sort }
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (entry.source, entry.start)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn interleaves_recent_entries() {
+        let sources = vec![
+            vec![history(30, None, 0), history(10, None, 0)],
+            vec![history(20, None, 1), history(5, None, 1)],
+        ];
+        assert_eq!(
+            interleave(sources, SortMode::Recent),
+            vec![(0, 30), (1, 20), (0, 10), (1, 5)]
+        );
+    }
+
+    #[test]
+    fn interleaves_by_frecency_then_start() {
+        let sources = vec![
+            vec![history(10, Some(3.0), 0), history(40, Some(1.0), 0)],
+            vec![history(20, Some(2.0), 1), history(30, Some(1.0), 1)],
+        ];
+        assert_eq!(
+            interleave(sources, SortMode::Frecency),
+            vec![(0, 10), (1, 20), (0, 40), (1, 30)]
+        );
+    }
+
+    #[test]
+    fn keeps_source_order_for_ties() {
+        let first = history(10, Some(1.0), 0);
+        let second = history(10, Some(1.0), 1);
+        for sort in [SortMode::Recent, SortMode::Frecency] {
+            assert!(!comes_before(&first, &second, sort));
+            assert!(!comes_before(&second, &first, sort));
+        }
+        let sources = vec![
+            vec![history(10, None, 0)],
+            vec![history(10, None, 1)],
+            vec![history(10, None, 2)],
+        ];
+        assert_eq!(
+            interleave(sources, SortMode::Recent),
+            vec![(0, 10), (1, 10), (2, 10)]
+        );
+    }
+
+    #[test]
+    fn continues_after_exhausted_source() {
+        let sources = vec![
+            vec![history(50, None, 0)],
+            vec![],
+            vec![
+                history(40, None, 2),
+                history(30, None, 2),
+                history(20, None, 2),
+            ],
+        ];
+        assert_eq!(
+            interleave(sources, SortMode::Recent),
+            vec![(0, 50), (2, 40), (2, 30), (2, 20)]
+        );
+    }
+
+    fn add_entry(conn: &mut Connection, cmd: &str, host: &str, session: i64, start: i64) {
+        let tx = conn.transaction().unwrap();
+        insert_entry(&tx, cmd, host, "/tmp", session, start, None).unwrap();
+        tx.commit().unwrap();
+    }
+
+    fn count(conn: &Connection, table: &str) -> i64 {
+        conn.query_row(&format!("select count(*) from {}", table), [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    }
+
+    fn history_database() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        add_entry(&mut conn, "ls", "desktop", 1, 100);
+        add_entry(&mut conn, "ls", "desktop", 1, 110);
+        add_entry(&mut conn, "make", "laptop", 2, 120);
+        add_entry(&mut conn, "make", "desktop", 1, 130);
+        conn
+    }
+
+    #[test]
+    fn deletes_single_execution() {
+        let mut conn = history_database();
+        let id: i64 = conn
+            .query_row("select id from history where start_time = 100", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(delete_entry(&mut conn, id).unwrap(), 1);
+        let starts: Vec<i64> = conn
+            .prepare("select start_time from history order by start_time")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(starts, vec![110, 120, 130]);
+        assert_eq!(count(&conn, "commands"), 2);
+        assert_eq!(count(&conn, "places"), 2);
+    }
+
+    #[test]
+    fn deletes_every_execution_of_command() {
+        let mut conn = history_database();
+        assert_eq!(delete_command(&mut conn, "make").unwrap(), 2);
+        assert_eq!(count(&conn, "history"), 2);
+        let argv: Vec<String> = conn
+            .prepare("select argv from commands")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(argv, vec!["ls"]);
+        // the place of laptop is left without entries, the one of desktop is still used
+        let hosts: Vec<String> = conn
+            .prepare("select host from places")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(hosts, vec!["desktop"]);
+    }
+
+    #[test]
+    fn reads_neighbours_in_session() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        for (cmd, start) in [
+            ("a", 10),
+            ("b", 20),
+            ("c", 30),
+            ("d", 40),
+            ("e", 50),
+            ("f", 60),
+        ] {
+            add_entry(&mut conn, cmd, "box", 1, start);
+        }
+        add_entry(&mut conn, "other session", "box", 2, 35);
+        add_entry(&mut conn, "other host", "laptop", 1, 45);
+        let id: i64 = conn
+            .query_row(
+                "select history.id from history join commands on history.command_id = commands.id
+                where commands.argv = 'd'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        let (before, after) = session_neighbours(&conn, id, 1, "box", 40, 2).unwrap();
+        let commands = |neighbours: &[Neighbour]| -> Vec<(String, u64)> {
+            neighbours
+                .iter()
+                .map(|neighbour| (neighbour.cmd.clone(), neighbour.start))
+                .collect()
+        };
+        assert_eq!(
+            commands(&before),
+            vec![("b".to_string(), 20), ("c".to_string(), 30)]
+        );
+        assert_eq!(
+            commands(&after),
+            vec![("e".to_string(), 50), ("f".to_string(), 60)]
+        );
+    }
+
+    #[test]
+    fn requires_a_database() {
+        assert_eq!(
+            main_database(String::new()).unwrap_err().to_string(),
+            "No database, set HISTDB_FILE or `database`"
+        );
+        assert_eq!(main_database("a.db".to_string()).unwrap(), "a.db");
+    }
+
+    #[test]
+    fn merges_other_database() {
+        let other_path =
+            std::env::temp_dir().join(format!("histdb-merge-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&other_path);
+        let mut other = Connection::open(&other_path).unwrap();
+        create_schema(&other).unwrap();
+        add_entry(&mut other, "make", "laptop", 1, 100);
+        add_entry(&mut other, "ls", "laptop", 1, 110);
+        add_entry(&mut other, "ls", "laptop", 1, 110);
+        add_entry(&mut other, "git pull", "laptop", 2, 120);
+        drop(other);
+
+        let mut conn = Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        add_entry(&mut conn, "ls", "desktop", 5, 90);
+        add_entry(&mut conn, "make", "laptop", 1, 100);
+
+        let summary = merge_database(&mut conn, other_path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&other_path).unwrap();
+        assert_eq!(
+            summary,
+            MergeSummary {
+                commands: 1,
+                places: 0,
+                entries: 2,
+                duplicates: 2,
+            }
+        );
+        let count: i64 = conn
+            .query_row("select count(*) from history", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 4);
+    }
+}
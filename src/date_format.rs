@@ -0,0 +1,131 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::NaiveDate;
+
+/// Date and time formats of the entries, as strftime patterns
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DateFormat {
+    pub date: String,
+    pub time: String,
+    /// Widest text either of the formats produces
+    pub width: usize,
+}
+
+impl DateFormat {
+    pub fn new(date: &str, time: &str) -> Self {
+        DateFormat {
+            date: date.to_string(),
+            time: time.to_string(),
+            width: max_width(date).max(max_width(time)),
+        }
+    }
+
+    /// The formats usual for a locale like `de_DE.UTF-8`
+    pub fn for_locale(locale: &str) -> Self {
+        let name = locale.split(['.', '@']).next().unwrap_or_default();
+        let language = name.split('_').next().unwrap_or_default();
+        match (name, language) {
+            ("en_US" | "en_PH", _) => DateFormat::new("%m/%d/%Y", "%I:%M %p"),
+            ("en_CA", _) => DateFormat::new("%Y-%m-%d", "%I:%M %p"),
+            ("en_AU" | "en_NZ" | "en_IN", _) => DateFormat::new("%d/%m/%Y", "%I:%M %p"),
+            (_, "de" | "ru" | "pl" | "cs" | "sk" | "fi" | "nb" | "no" | "da" | "tr" | "uk") => {
+                DateFormat::new("%d.%m.%Y", "%H:%M")
+            }
+            (_, "nl") => DateFormat::new("%d-%m-%Y", "%H:%M"),
+            (_, "hu") => DateFormat::new("%Y.%m.%d", "%H:%M"),
+            (_, "ja" | "zh") => DateFormat::new("%Y/%m/%d", "%H:%M"),
+            (_, "sv" | "lt" | "ko") => DateFormat::iso(),
+            _ => DateFormat::non_us(),
+        }
+    }
+
+    /// Parse a setting, `us`, `non-us`, `iso`, `locale` or a strftime pattern for the date
+    pub fn parse(setting: &str, locale: &str) -> Result<Self, String> {
+        let locale_format = DateFormat::for_locale(locale);
+        match setting.to_lowercase().as_str() {
+            "us" => Ok(DateFormat::new("%m/%d/%Y", "%H:%M")),
+            "non-us" => Ok(DateFormat::non_us()),
+            "iso" => Ok(DateFormat::iso()),
+            "locale" => Ok(locale_format),
+            _ if setting.contains('%') => {
+                validate_pattern(setting)?;
+                Ok(DateFormat::new(setting, &locale_format.time))
+            }
+            _ => Err(format!(
+                "unknown date format `{}`, expected us, non-us, iso, locale or a strftime pattern",
+                setting
+            )),
+        }
+    }
+
+    /// The same formats with another time format
+    pub fn with_time(self, time: &str) -> Self {
+        DateFormat::new(&self.date, time)
+    }
+
+    fn non_us() -> Self {
+        DateFormat::new("%d/%m/%Y", "%H:%M")
+    }
+
+    fn iso() -> Self {
+        DateFormat::new("%Y-%m-%d", "%H:%M")
+    }
+}
+
+/// Check that chrono understands the strftime pattern
+pub fn validate_pattern(pattern: &str) -> Result<(), String> {
+    if StrftimeItems::new(pattern).any(|item| item == Item::Error) {
+        Err(format!("invalid strftime pattern `{}`", pattern))
+    } else {
+        Ok(())
+    }
+}
+
+/// Widest text of the pattern, trying every month, weekday and half of the day
+fn max_width(pattern: &str) -> usize {
+    let mut width = 0;
+    for month in 1..=12 {
+        for day in 22..=28 {
+            for hour in [0, 12] {
+                let sample = NaiveDate::from_ymd_opt(2000, month, day)
+                    .and_then(|date| date.and_hms_opt(hour, 0, 0))
+                    .unwrap();
+                width = width.max(sample.format(pattern).to_string().chars().count());
+            }
+        }
+    }
+    width
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_locales() {
+        assert_eq!(DateFormat::for_locale("en_US.UTF-8").time, "%I:%M %p");
+        assert_eq!(DateFormat::for_locale("de_DE.UTF-8").date, "%d.%m.%Y");
+        assert_eq!(DateFormat::for_locale("sv_SE").date, "%Y-%m-%d");
+        assert_eq!(DateFormat::for_locale("C"), DateFormat::non_us());
+        assert_eq!(DateFormat::for_locale(""), DateFormat::non_us());
+    }
+
+    #[test]
+    fn parses_settings() {
+        assert_eq!(DateFormat::parse("US", "").unwrap().date, "%m/%d/%Y");
+        assert_eq!(DateFormat::parse("iso", "").unwrap().date, "%Y-%m-%d");
+        assert_eq!(
+            DateFormat::parse("locale", "en_US.UTF-8").unwrap(),
+            DateFormat::for_locale("en_US.UTF-8")
+        );
+        assert_eq!(DateFormat::parse("%e %b", "de_DE").unwrap().date, "%e %b");
+        assert!(DateFormat::parse("%Q", "").is_err());
+        assert!(DateFormat::parse("european", "").is_err());
+    }
+
+    #[test]
+    fn measures_width() {
+        assert_eq!(DateFormat::non_us().width, 10);
+        assert_eq!(DateFormat::new("%d.%m.", "%I:%M %p").width, 8);
+        assert_eq!(DateFormat::new("%e %B", "%H:%M").width, 12);
+    }
+}
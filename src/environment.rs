@@ -1,23 +1,95 @@
+#![allow(clippy::needless_return)]
+
+use crate::column::{Column, DEFAULT_COLUMNS};
+use crate::config::config;
+use crate::date_format::DateFormat;
 use std::env;
+use std::path::Path;
+use std::sync::OnceLock;
+
+static DATE_FORMAT: OnceLock<DateFormat> = OnceLock::new();
 
-/// Get the default (which is non us! or the us date format)
-/// - [ ] Read from locale to determine default
-pub fn get_date_format() -> String {
-    let key = "HISTDB_FZF_FORCE_DATE_FORMAT";
-    let forced_dateformat = env::var(key).unwrap_or("non-us".to_string()).to_lowercase();
+/// Get a variable of the environment, unset if it is empty
+fn get_env(key: &str) -> Option<String> {
+    env::var(key).ok().filter(|value| !value.is_empty())
+}
+
+/// Get the date and time format: forced by `HISTDB_FZF_FORCE_DATE_FORMAT`, configured or usual for the locale
+pub fn get_date_format() -> &'static DateFormat {
+    DATE_FORMAT.get_or_init(|| {
+        let locale = get_locale();
+        let date_format = match get_env("HISTDB_FZF_FORCE_DATE_FORMAT") {
+            Some(forced) => {
+                DateFormat::parse(&forced, &locale).unwrap_or(DateFormat::for_locale("C"))
+            }
+            None => config()
+                .date_format
+                .as_ref()
+                .and_then(|setting| DateFormat::parse(setting, &locale).ok())
+                .unwrap_or(DateFormat::for_locale(&locale)),
+        };
+        match &config().time_format {
+            Some(time) => date_format.with_time(time),
+            None => date_format,
+        }
+    })
+}
+
+/// Get the locale for dates and times, the same way the C library does
+fn get_locale() -> String {
+    ["LC_ALL", "LC_TIME", "LANG"]
+        .iter()
+        .find_map(|key| get_env(key))
+        .unwrap_or_default()
+}
+
+/// Get the columns shown in front of the command, in their order
+pub fn get_columns() -> &'static [Column] {
+    config().columns.as_deref().unwrap_or(&DEFAULT_COLUMNS)
+}
 
-    if forced_dateformat == "us" {
-        return "%m/%d/%Y".to_string();
-    } else {
-        return "%d/%m/%Y".to_string();
+/// Get how many commands before and after the selected one the preview shows
+pub fn get_preview_neighbours() -> usize {
+    config().preview_neighbours.unwrap_or(3)
+}
+
+/// Get the histdb files of the configuration, with the first one replaced by HISTDB_FILE.
+/// zsh-histdb records into HISTDB_FILE, so it is a single path
+pub fn get_histdb_databases() -> Vec<String> {
+    let key = "HISTDB_FILE";
+    let mut databases = config()
+        .database
+        .as_ref()
+        .map(|databases| databases.paths())
+        .unwrap_or_default();
+    if let Some(file) = get_env(key) {
+        match databases.first_mut() {
+            Some(main) => *main = file,
+            None => databases.push(file),
+        }
     }
+    databases
 }
 
-/// Get the histdb file from the environment
+/// Get the main histdb file, the one changes are written to
 pub fn get_histdb_database() -> String {
-    let key = "HISTDB_FILE";
-    let db_file = env::var(key).unwrap_or(String::from(""));
-    return db_file.to_string();
+    get_histdb_databases()
+        .into_iter()
+        .next()
+        .unwrap_or_default()
+}
+
+/// Name of a database in the preview, the name of its file without the extension
+pub fn get_database_label(source: usize) -> String {
+    let databases = get_histdb_databases();
+    let path = databases
+        .get(source)
+        .map(String::as_str)
+        .unwrap_or_default();
+    Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_string())
 }
 
 /// Get the histdb session from the environment
@@ -34,6 +106,14 @@ pub fn get_current_dir() -> String {
     return cdir_string.to_string();
 }
 
+/// Get the root of the git work tree containing the directory
+pub fn get_repository_root(dir: &str) -> Option<String> {
+    Path::new(dir)
+        .ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .map(|dir| dir.to_string_lossy().to_string())
+}
+
 /// Get the current histdb host from the environment
 pub fn get_current_host() -> String {
     let mut host = env::var("HISTDB_HOST").unwrap_or(String::from(""));
@@ -44,9 +124,8 @@ pub fn get_current_host() -> String {
 }
 
 pub fn get_nosort_option() -> bool {
-    let nosort = env::var("HISTDB_NOSORT").unwrap_or(String::from("false"));
-    if nosort.to_lowercase() == "true" || nosort == "1" {
-        return true;
+    match get_env("HISTDB_NOSORT") {
+        Some(nosort) => nosort.to_lowercase() == "true" || nosort == "1",
+        None => config().nosort.unwrap_or(false),
     }
-    return false;
 }
@@ -0,0 +1,13 @@
+use clap::ValueEnum;
+
+/// Which commands to show, depending on their exit status
+#[derive(PartialEq, Eq, Hash, Debug, ValueEnum, Copy, Clone, Default)]
+pub enum ExitFilter {
+    /// All commands
+    #[default]
+    All,
+    /// Only commands exiting with status 0
+    Success,
+    /// Only commands exiting with another status
+    Failed,
+}
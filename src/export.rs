@@ -0,0 +1,119 @@
+use crate::app_state::AppState;
+use crate::database::read_history;
+use crate::history::History;
+use crate::location::Location;
+use crate::query::build_query_string;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::io::{self, ErrorKind, Write};
+
+/// How the entries are written by `export`
+#[derive(PartialEq, Eq, Debug, ValueEnum, Copy, Clone, Default)]
+pub enum ExportFormat {
+    /// One JSON object per line
+    #[default]
+    Jsonl,
+    /// Comma separated values with a header line
+    Csv,
+}
+
+/// Columns of the CSV export, the fields of `ExportRow`
+const CSV_HEADER: &str = "id,cmd,start,exit_status,duration,session,host,dir";
+
+/// A single execution of a command as exported
+#[derive(Serialize)]
+struct ExportRow<'a> {
+    id: i64,
+    cmd: &'a str,
+    start: u64,
+    exit_status: Option<i64>,
+    duration: Option<i64>,
+    session: i64,
+    host: &'a str,
+    dir: &'a str,
+}
+
+impl<'a> ExportRow<'a> {
+    fn new(entry: &'a History) -> Self {
+        ExportRow {
+            id: entry.id,
+            cmd: &entry.cmd,
+            start: entry.start,
+            exit_status: entry.exit_status,
+            duration: entry.duration,
+            session: entry.session,
+            host: &entry.host,
+            dir: &entry.dir,
+        }
+    }
+
+    fn csv_line(&self) -> String {
+        let optional = |value: Option<i64>| value.map(|v| v.to_string()).unwrap_or_default();
+        [
+            self.id.to_string(),
+            csv_escape(self.cmd),
+            self.start.to_string(),
+            optional(self.exit_status),
+            optional(self.duration),
+            self.session.to_string(),
+            csv_escape(self.host),
+            csv_escape(self.dir),
+        ]
+        .join(",")
+    }
+}
+
+/// Write every execution in the location to stdout
+pub fn export(
+    location: &Location,
+    app_state: &AppState,
+    format: ExportFormat,
+) -> Result<(), String> {
+    let entries = read_history(
+        &build_query_string(location, false, app_state),
+        app_state.sort,
+    )
+    .map_err(|e| format!("Could not read database: {}", e))?;
+
+    let mut out = io::stdout().lock();
+    let mut write = |line: &str| match writeln!(out, "{}", line) {
+        Err(e) if e.kind() == ErrorKind::BrokenPipe => Ok(false),
+        result => result.map(|_| true).map_err(|e| e.to_string()),
+    };
+    if format == ExportFormat::Csv && !write(CSV_HEADER)? {
+        return Ok(());
+    }
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Could not read entry: {}", e))?;
+        let row = ExportRow::new(&entry);
+        let line = match format {
+            ExportFormat::Jsonl => serde_json::to_string(&row).map_err(|e| e.to_string())?,
+            ExportFormat::Csv => row.csv_line(),
+        };
+        if !write(&line)? {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// Quote the value if it contains a separator, quote or line break
+fn csv_escape(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_csv() {
+        assert_eq!(csv_escape("ls -la"), "ls -la");
+        assert_eq!(csv_escape("echo a,b"), "\"echo a,b\"");
+        assert_eq!(csv_escape("echo \"hi\"\necho"), "\"echo \"\"hi\"\"\necho\"");
+    }
+}
@@ -0,0 +1,383 @@
+use ratatui::style::{Color, Modifier, Style};
+
+/// The kinds of words and characters of a command line that are colored
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum Token {
+    Command,
+    Flag,
+    String,
+    Variable,
+    Operator,
+    Redirection,
+    Comment,
+}
+
+/// Stands for a line break of a multi-line command in the list
+pub const NEWLINE_MARKER: char = '↵';
+
+/// Words after which the next word is a command again
+const PRECOMMANDS: [&str; 18] = [
+    "sudo", "doas", "time", "nohup", "nice", "env", "exec", "command", "builtin", "noglob", "if",
+    "then", "else", "elif", "do", "while", "until", "!",
+];
+
+impl Token {
+    /// Style of the token in the list
+    pub fn style(self) -> Style {
+        match self {
+            Token::Command => Style::default()
+                .fg(Color::Green)
+                .add_modifier(Modifier::BOLD),
+            Token::Flag => Style::default().fg(Color::Cyan),
+            Token::String => Style::default().fg(Color::Yellow),
+            Token::Variable => Style::default().fg(Color::Magenta),
+            Token::Operator => Style::default()
+                .fg(Color::Blue)
+                .add_modifier(Modifier::BOLD),
+            Token::Redirection => Style::default().fg(Color::Blue),
+            Token::Comment => Style::default().fg(Color::DarkGray),
+        }
+    }
+
+    /// ANSI escape sequence of the token in the preview
+    fn ansi(self) -> &'static str {
+        match self {
+            Token::Command => "\x1b[1;32m",
+            Token::Flag => "\x1b[36m",
+            Token::String => "\x1b[33m",
+            Token::Variable => "\x1b[35m",
+            Token::Operator => "\x1b[1;34m",
+            Token::Redirection => "\x1b[34m",
+            Token::Comment => "\x1b[90m",
+        }
+    }
+}
+
+/// Split the command line into colored ranges, as char indices
+pub fn tokenize(cmd: &str) -> Vec<(usize, usize, Token)> {
+    let chars: Vec<char> = cmd.chars().collect();
+    let mut tokens = Vec::new();
+    let mut expect_command = true;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let word_start = i == 0 || chars[i - 1].is_whitespace();
+        if c.is_whitespace() {
+            if c == '\n' {
+                expect_command = true;
+            }
+            i += 1;
+        } else if c == NEWLINE_MARKER {
+            tokens.push((i, i + 1, Token::Operator));
+            expect_command = true;
+            i += 1;
+        } else if c == '#' && word_start {
+            let end = find(&chars, i, |c| c == '\n' || c == NEWLINE_MARKER);
+            tokens.push((i, end, Token::Comment));
+            i = end;
+        } else if let Some(length) = redirection_length(&chars, i) {
+            tokens.push((i, i + length, Token::Redirection));
+            i += length;
+        } else if let Some(length) = operator_length(&chars, i) {
+            tokens.push((i, i + length, Token::Operator));
+            expect_command = true;
+            i += length;
+        } else {
+            let (end, word_tokens, plain) = scan_word(&chars, i);
+            let word: String = chars[i..end].iter().collect();
+            let plain_kind = if expect_command && !is_assignment(&word) {
+                Some(Token::Command)
+            } else if word.starts_with('-') {
+                Some(Token::Flag)
+            } else {
+                None
+            };
+            if let Some(kind) = plain_kind {
+                tokens.extend(plain.into_iter().map(|(start, end)| (start, end, kind)));
+            }
+            tokens.extend(word_tokens);
+            if expect_command && !is_assignment(&word) {
+                expect_command = PRECOMMANDS.contains(&word.as_str());
+            }
+            i = end;
+        }
+    }
+    tokens.sort_by_key(|(start, _, _)| *start);
+    tokens
+}
+
+/// Color the command line for the terminal
+pub fn highlight_ansi(cmd: &str) -> String {
+    let mut highlighted = String::new();
+    let mut tokens = tokenize(cmd).into_iter().peekable();
+    let mut current: Option<(usize, Token)> = None;
+    for (i, c) in cmd.chars().enumerate() {
+        if let Some((end, _)) = current {
+            if i == end {
+                highlighted.push_str("\x1b[0m");
+                current = None;
+            }
+        }
+        if let Some((start, end, token)) = tokens.peek().copied() {
+            if start == i {
+                highlighted.push_str(token.ansi());
+                current = Some((end, token));
+                tokens.next();
+            }
+        }
+        highlighted.push(c);
+    }
+    if current.is_some() {
+        highlighted.push_str("\x1b[0m");
+    }
+    highlighted
+}
+
+/// Index of the first char from `start` on matching, or the end
+fn find(chars: &[char], start: usize, predicate: impl Fn(char) -> bool) -> usize {
+    (start..chars.len())
+        .find(|&i| predicate(chars[i]))
+        .unwrap_or(chars.len())
+}
+
+fn is_assignment(word: &str) -> bool {
+    match word.find('=') {
+        Some(position) => {
+            position > 0
+                && word[..position]
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '_')
+        }
+        None => false,
+    }
+}
+
+fn is_operator_char(c: char) -> bool {
+    matches!(c, '|' | '&' | ';' | '<' | '>' | '(' | ')')
+}
+
+fn operator_length(chars: &[char], i: usize) -> Option<usize> {
+    let next = chars.get(i + 1).copied();
+    match (chars[i], next) {
+        ('&', Some('&')) | ('|', Some('|')) | ('|', Some('&')) | (';', Some(';')) => Some(2),
+        ('|' | '&' | ';' | '(' | ')', _) => Some(1),
+        _ => None,
+    }
+}
+
+/// Length of a redirection like `>`, `2>>`, `&>` or `<<<`
+fn redirection_length(chars: &[char], i: usize) -> Option<usize> {
+    let mut j = i;
+    while j < chars.len() && chars[j].is_ascii_digit() {
+        j += 1;
+    }
+    if j == chars.len() {
+        return None;
+    }
+    let start = j;
+    match (chars[j], chars.get(j + 1).copied()) {
+        ('&', Some('>')) if j == i => j += 2,
+        ('>' | '<', _) => j += 1,
+        _ => return None,
+    }
+    while j < chars.len() && j - start < 3 && matches!(chars[j], '>' | '<' | '&' | '|') {
+        j += 1;
+    }
+    if chars[j - 1] == '&' {
+        // duplicating a file descriptor, like 2>&1 or >&-
+        while j < chars.len() && (chars[j].is_ascii_digit() || chars[j] == '-') {
+            j += 1;
+        }
+    }
+    Some(j - i)
+}
+
+/// The end of a word, its quoted and variable parts and its plain parts
+type ScannedWord = (usize, Vec<(usize, usize, Token)>, Vec<(usize, usize)>);
+
+/// Scan a word, returning its end, the quoted and variable parts and the plain parts
+fn scan_word(chars: &[char], start: usize) -> ScannedWord {
+    let mut tokens = Vec::new();
+    let mut plain: Vec<(usize, usize)> = Vec::new();
+    let mut push_plain = |from: usize, to: usize| {
+        if from == to {
+            return;
+        }
+        match plain.last_mut() {
+            Some(last) if last.1 == from => last.1 = to,
+            _ => plain.push((from, to)),
+        }
+    };
+    let mut i = start;
+    while i < chars.len() && !chars[i].is_whitespace() && !is_operator_char(chars[i]) {
+        match chars[i] {
+            '\\' => {
+                let end = (i + 2).min(chars.len());
+                push_plain(i, end);
+                i = end;
+            }
+            '\'' => {
+                let end = (find(chars, i + 1, |c| c == '\'') + 1).min(chars.len());
+                tokens.push((i, end, Token::String));
+                i = end;
+            }
+            '"' => {
+                let end = (closing_quote(chars, i + 1, '"') + 1).min(chars.len());
+                tokens.push((i, end, Token::String));
+                i = end;
+            }
+            '$' if chars.get(i + 1) == Some(&'\'') => {
+                let end = (closing_quote(chars, i + 2, '\'') + 1).min(chars.len());
+                tokens.push((i, end, Token::String));
+                i = end;
+            }
+            '$' => {
+                let end = variable_end(chars, i);
+                if end > i + 1 {
+                    tokens.push((i, end, Token::Variable));
+                } else {
+                    push_plain(i, end);
+                }
+                i = end;
+            }
+            _ => {
+                push_plain(i, i + 1);
+                i += 1;
+            }
+        }
+    }
+    (i, tokens, plain)
+}
+
+/// Index of the quote ending a string, skipping escaped quotes
+fn closing_quote(chars: &[char], mut i: usize, quote: char) -> usize {
+    while i < chars.len() && chars[i] != quote {
+        i += if chars[i] == '\\' { 2 } else { 1 };
+    }
+    i.min(chars.len())
+}
+
+/// End of `$name`, `${...}`, `$(...)` or special parameters like `$?`
+fn variable_end(chars: &[char], start: usize) -> usize {
+    let closing = match chars.get(start + 1) {
+        Some('{') => Some(('{', '}')),
+        Some('(') => Some(('(', ')')),
+        _ => None,
+    };
+    if let Some((open, close)) = closing {
+        let mut depth = 0;
+        for (i, &c) in chars.iter().enumerate().skip(start + 1) {
+            if c == open {
+                depth += 1;
+            } else if c == close {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+        }
+        return chars.len();
+    }
+    match chars.get(start + 1) {
+        Some(c) if matches!(c, '?' | '!' | '#' | '*' | '@' | '$' | '-') || c.is_ascii_digit() => {
+            start + 2
+        }
+        _ => find(chars, start + 1, |c| !(c.is_alphanumeric() || c == '_')),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The highlighted parts of the command with their kind
+    fn parts(cmd: &str) -> Vec<(String, Token)> {
+        let chars: Vec<char> = cmd.chars().collect();
+        tokenize(cmd)
+            .into_iter()
+            .map(|(start, end, token)| (chars[start..end].iter().collect(), token))
+            .collect()
+    }
+
+    fn part(text: &str, token: Token) -> (String, Token) {
+        (text.to_string(), token)
+    }
+
+    #[test]
+    fn highlights_commands_and_flags() {
+        assert_eq!(
+            parts("git commit -m \"fix it\""),
+            vec![
+                part("git", Token::Command),
+                part("-m", Token::Flag),
+                part("\"fix it\"", Token::String),
+            ]
+        );
+    }
+
+    #[test]
+    fn highlights_pipes_and_redirections() {
+        assert_eq!(
+            parts("ls -l | grep $HOME > out.txt 2>&1 && echo done"),
+            vec![
+                part("ls", Token::Command),
+                part("-l", Token::Flag),
+                part("|", Token::Operator),
+                part("grep", Token::Command),
+                part("$HOME", Token::Variable),
+                part(">", Token::Redirection),
+                part("2>&1", Token::Redirection),
+                part("&&", Token::Operator),
+                part("echo", Token::Command),
+            ]
+        );
+    }
+
+    #[test]
+    fn highlights_precommands_assignments_and_comments() {
+        assert_eq!(
+            parts("LANG=C sudo make 'all' ${TARGET} # build"),
+            vec![
+                part("sudo", Token::Command),
+                part("make", Token::Command),
+                part("'all'", Token::String),
+                part("${TARGET}", Token::Variable),
+                part("# build", Token::Comment),
+            ]
+        );
+    }
+
+    #[test]
+    fn survives_unterminated_quotes() {
+        assert_eq!(
+            parts("echo \"open"),
+            vec![part("echo", Token::Command), part("\"open", Token::String)]
+        );
+        assert_eq!(
+            parts("echo $("),
+            vec![part("echo", Token::Command), part("$(", Token::Variable)]
+        );
+    }
+
+    #[test]
+    fn starts_commands_after_line_breaks() {
+        assert_eq!(
+            parts("cd /tmp # go ↵ ls"),
+            vec![
+                part("cd", Token::Command),
+                part("# go ", Token::Comment),
+                part("↵", Token::Operator),
+                part("ls", Token::Command),
+            ]
+        );
+    }
+
+    #[test]
+    fn colors_for_the_terminal() {
+        assert_eq!(
+            highlight_ansi("ls -a"),
+            "\x1b[1;32mls\x1b[0m \x1b[36m-a\x1b[0m"
+        );
+    }
+}
@@ -1,13 +1,23 @@
 extern crate skim;
+use crate::column::Column;
+use crate::database::{read_neighbours, Neighbour};
 use crate::environment::*;
+use crate::highlight::{highlight_ansi, tokenize, NEWLINE_MARKER};
+use crate::query::LIST_SEPARATOR;
 use chrono::Timelike;
 use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
 use humantime::format_duration;
+use ratatui::style::{Color, Style};
+use ratatui::text::{Line, Span};
+use rusqlite::Row;
+use serde::Serialize;
 use skim::prelude::*;
+use std::sync::OnceLock;
 use std::time::Duration;
-use textwrap::fill;
+use textwrap::core::display_width;
+use textwrap::{fill, Options};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct History {
     pub id: i64,
     pub cmd: String,
@@ -15,42 +25,310 @@ pub struct History {
     pub exit_status: Option<i64>,
     pub duration: Option<i64>,
     pub count: i64,
+    pub first_start: u64,
+    pub success_rate: Option<f64>,
+    pub frecency: Option<f64>,
     pub session: i64,
     pub host: String,
     pub dir: String,
+    pub hosts: Vec<String>,
+    pub dirs: Vec<String>,
+    /// The command on a single line, as shown in the list
+    #[serde(skip)]
+    list_cmd: String,
+    /// Index of the database the entry was read from, 0 is the main database
+    #[serde(skip)]
+    pub source: usize,
+    #[serde(skip)]
+    pub relative_time: bool,
+    /// Commands run before and after this one in the session, read for the preview
+    #[serde(skip)]
+    neighbours: OnceLock<(Vec<Neighbour>, Vec<Neighbour>)>,
+    #[serde(skip)]
     pub searchrange: [(usize, usize); 1],
 }
 
 impl History {
-    pub const FORMAT_DATE_LENGTH: usize = 10;
-    pub const COMMAND_START: usize = (History::FORMAT_DATE_LENGTH + 1);
-
+    #[allow(clippy::needless_return)]
     pub fn command(&self) -> &String {
         return &self.cmd;
     }
+
+    pub fn from_row(row: &Row) -> rusqlite::Result<History> {
+        let cmd: String = row.get("cmd")?;
+        let start: i64 = row.get("start")?;
+        let first_start: i64 = row.get("first_start")?;
+        let mut history = History {
+            id: row.get("id")?,
+            list_cmd: History::flatten(&cmd),
+            cmd,
+            start: start as u64,
+            exit_status: row.get("exit_status")?,
+            duration: row.get("duration")?,
+            count: row.get("count")?,
+            first_start: first_start as u64,
+            success_rate: row.get("success_rate")?,
+            frecency: row.get("frecency")?,
+            session: row.get("session")?,
+            host: row.get("host")?,
+            dir: row.get("dir")?,
+            hosts: History::split_list(row.get("hosts")?),
+            dirs: History::split_list(row.get("dirs")?),
+            source: 0,
+            relative_time: false,
+            neighbours: OnceLock::new(),
+            searchrange: [(0, 0)],
+        };
+        history.update_searchrange();
+        Ok(history)
+    }
+
+    /// Show the age instead of the date in the list
+    pub fn set_relative_time(&mut self, relative_time: bool) {
+        self.relative_time = relative_time;
+        self.update_searchrange();
+    }
+
+    /// Restrict matching to the command, behind the columns
+    fn update_searchrange(&mut self) {
+        let command_start: usize = self.columns().iter().map(|(text, _)| text.len() + 1).sum();
+        self.searchrange = [(command_start, command_start + self.list_cmd.len())];
+    }
+
+    /// The commands around this one in its session, read from the database on first use
+    fn neighbours(&self) -> &(Vec<Neighbour>, Vec<Neighbour>) {
+        self.neighbours.get_or_init(|| {
+            read_neighbours(
+                self.source,
+                self.id,
+                self.session,
+                &self.host,
+                self.start,
+                get_preview_neighbours(),
+            )
+            .unwrap_or_default()
+        })
+    }
+
+    /// The command on a single line: line breaks shown as `↵`, runs of whitespace
+    /// collapsed and overlong commands cut at a word
+    fn flatten(cmd: &str) -> String {
+        let mut flat = String::new();
+        let mut continued = false;
+        for line in cmd.lines() {
+            let (line, continues) = match line.trim_end().strip_suffix('\\') {
+                Some(line) => (line, true),
+                None => (line, false),
+            };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if !words.is_empty() {
+                if !flat.is_empty() && !continued {
+                    flat.push_str(&format!(" {}", NEWLINE_MARKER));
+                }
+                if !flat.is_empty() {
+                    flat.push(' ');
+                }
+                flat.push_str(&words.join(" "));
+            }
+            continued = continues;
+        }
+        if flat.chars().count() > History::MAX_LIST_LENGTH {
+            let mut cut: String = flat.chars().take(History::MAX_LIST_LENGTH).collect();
+            if let Some(space) = cut.rfind(' ').filter(|&space| space > cut.len() / 2) {
+                cut.truncate(space);
+            }
+            cut.push('…');
+            flat = cut;
+        }
+        flat
+    }
+
+    /// Split a list of the query into its distinct values
+    fn split_list(list: Option<String>) -> Vec<String> {
+        let mut values: Vec<String> = Vec::new();
+        for value in list.unwrap_or_default().split(LIST_SEPARATOR) {
+            if !value.is_empty() && !values.iter().any(|v| v == value) {
+                values.push(value.to_string());
+            }
+        }
+        values
+    }
+
+    /// An execution of the command on box in /tmp, for tests
+    #[cfg(test)]
+    pub fn example(id: i64, cmd: &str, start: u64) -> History {
+        let mut history = History {
+            id,
+            cmd: cmd.to_string(),
+            start,
+            exit_status: None,
+            duration: None,
+            count: 1,
+            first_start: start,
+            success_rate: None,
+            frecency: None,
+            session: 1,
+            host: "box".to_string(),
+            dir: "/tmp".to_string(),
+            hosts: Vec::new(),
+            dirs: Vec::new(),
+            list_cmd: History::flatten(cmd),
+            source: 0,
+            relative_time: false,
+            neighbours: OnceLock::new(),
+            searchrange: [(0, 0)],
+        };
+        history.update_searchrange();
+        history
+    }
 }
 
 impl History {
-    fn format_date(&self, full: bool) -> String {
-        let naive = NaiveDateTime::from_timestamp_opt(self.start as i64, 0).unwrap_or_default();
+    /// Longest command in the list, longer ones are cut
+    const MAX_LIST_LENGTH: usize = 500;
+    /// Width of the longest age, `11mo ago`
+    const AGE_WIDTH: usize = 8;
+    /// Width of the longest compact duration, `59m59s`
+    const DURATION_WIDTH: usize = 6;
+
+    fn format_date(timestamp: u64, full: bool) -> String {
+        let naive = NaiveDateTime::from_timestamp_opt(timestamp as i64, 0).unwrap_or_default();
         let start_time: DateTime<Local> = Local.from_utc_datetime(&naive);
         let current_time: DateTime<Local> = Local::now();
         let seconds_since_midnight = (current_time.hour() * 3600
             + current_time.minute() * 60
             + current_time.second()) as i64;
         let day_beginning = current_time.timestamp() - seconds_since_midnight;
+        let date_format = get_date_format();
         if full {
-            let mut dateinfo = String::from("");
-            dateinfo.push_str(&get_date_format());
-            dateinfo.push_str(" %H:%M");
-            return format!("{}", start_time.format(&dateinfo));
+            let dateinfo = format!("{} {}", date_format.date, date_format.time);
+            format!("{}", start_time.format(&dateinfo))
         } else if start_time.timestamp() > day_beginning {
-            return format!("{}", start_time.format("%H:%M"));
+            format!("{}", start_time.format(&date_format.time))
+        } else {
+            format!("{}", start_time.format(&date_format.date))
+        }
+    }
+
+    /// Time since the timestamp in its largest unit, e.g. `5m ago`
+    fn format_age(timestamp: u64, now: u64) -> String {
+        let seconds = now.saturating_sub(timestamp);
+        let (value, unit) = match seconds {
+            0..=59 => (seconds, "s"),
+            60..=3599 => (seconds / 60, "m"),
+            3600..=86399 => (seconds / 3600, "h"),
+            86400..=604799 => (seconds / 86400, "d"),
+            604800..=2629799 => (seconds / 604800, "w"),
+            2629800..=31557599 => (seconds / 2629800, "mo"),
+            _ => (seconds / 31557600, "y"),
+        };
+        format!("{}{} ago", value, unit)
+    }
+
+    /// The date or age in the list, padded so the commands line up
+    fn date_column(&self) -> String {
+        let width = get_date_format().width;
+        if self.relative_time {
+            let now = Local::now().timestamp() as u64;
+            format!(
+                "{:width$}",
+                History::format_age(self.start, now),
+                width = width.max(History::AGE_WIDTH)
+            )
         } else {
-            return format!("{}", start_time.format(&get_date_format()));
+            format!(
+                "{:width$}",
+                History::format_date(self.start, false),
+                width = width
+            )
+        }
+    }
+
+    /// Runtime in its two largest units, e.g. `1h5m`
+    fn format_compact_duration(seconds: i64) -> String {
+        let seconds = seconds.max(0);
+        match seconds {
+            0..=59 => format!("{}s", seconds),
+            60..=3599 => format!("{}m{}s", seconds / 60, seconds % 60),
+            3600..=86399 => format!("{}h{}m", seconds / 3600, seconds % 3600 / 60),
+            _ => format!("{}d{}h", seconds / 86400, seconds % 86400 / 3600),
+        }
+    }
+
+    /// The columns in front of the command with their style in the list
+    fn columns(&self) -> Vec<(String, Style)> {
+        get_columns()
+            .iter()
+            .map(|column| match column {
+                Column::Date => (self.date_column(), Style::default()),
+                Column::ExitStatus => match self.exit_status {
+                    Some(0) => ("✓".to_string(), Style::default().fg(Color::Green)),
+                    Some(_) => ("✗".to_string(), Style::default().fg(Color::Red)),
+                    None => (" ".to_string(), Style::default()),
+                },
+                Column::Duration => (
+                    format!(
+                        "{:>width$}",
+                        self.duration
+                            .map(History::format_compact_duration)
+                            .unwrap_or_default(),
+                        width = History::DURATION_WIDTH
+                    ),
+                    Style::default().fg(Color::DarkGray),
+                ),
+            })
+            .collect()
+    }
+
+    /// The session around this command, with this command highlighted
+    fn format_neighbours(&self, width: usize) -> String {
+        let (before, after) = self.neighbours();
+        if before.is_empty() && after.is_empty() {
+            return String::new();
         }
+        let line = |start: u64, cmd: &str| {
+            let line = format!(
+                "{:width$} {}",
+                History::format_date(start, false),
+                History::flatten(cmd),
+                width = get_date_format().width
+            );
+            line.chars()
+                .take(width.saturating_sub(2))
+                .collect::<String>()
+        };
+        let mut information = String::from("\n\x1b[1mSession Context\x1b[0m\n\n");
+        for neighbour in before {
+            information.push_str(&format!("  {}\n", line(neighbour.start, &neighbour.cmd)));
+        }
+        information.push_str(&format!(
+            "\x1b[1;33m> {}\x1b[0m\n",
+            line(self.start, &self.cmd)
+        ));
+        for neighbour in after {
+            information.push_str(&format!("  {}\n", line(neighbour.start, &neighbour.cmd)));
+        }
+        information.push('\n');
+        information
     }
 
+    /// The command as it was typed, long lines wrapped at their own indentation
+    fn format_command(&self, width: usize) -> String {
+        highlight_ansi(&self.cmd)
+            .lines()
+            .map(|line| {
+                if display_width(line) <= width {
+                    return line.to_string();
+                }
+                let indent_length = line.len() - line.trim_start().len();
+                let indent = &line[..indent_length];
+                fill(line, Options::new(width).subsequent_indent(indent))
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[allow(clippy::unnecessary_unwrap)]
     fn format_or_none(x: Option<i64>) -> String {
         if x.is_some() {
             format!("{}", x.unwrap())
@@ -59,6 +337,7 @@ impl History {
         }
     }
 
+    #[allow(clippy::unnecessary_unwrap)]
     fn format_duration(&self) -> String {
         if self.duration.is_some() {
             let duration = Duration::from_secs(self.duration.unwrap() as u64);
@@ -71,10 +350,31 @@ impl History {
 
 impl SkimItem for History {
     fn text(&self) -> Cow<'_, str> {
-        let information = format!("{:10} {}", self.format_date(false), self.cmd);
+        let mut information = String::new();
+        for (text, _) in self.columns() {
+            information.push_str(&text);
+            information.push(' ');
+        }
+        information.push_str(&self.list_cmd);
         Cow::Owned(information)
     }
 
+    fn display<'a>(&'a self, context: DisplayContext) -> Line<'a> {
+        let base_style = context.base_style;
+        let mut styles = Vec::new();
+        let mut start = 0;
+        for (text, style) in self.columns() {
+            let end = start + text.chars().count();
+            styles.push((start, end, style));
+            start = end + 1;
+        }
+        for (token_start, token_end, token) in tokenize(&self.list_cmd) {
+            styles.push((start + token_start, start + token_end, token.style()));
+        }
+        apply_styles(context.to_line(self.text()), &styles, base_style)
+    }
+
+    #[allow(clippy::useless_conversion)]
     fn preview(&self, _context: PreviewContext) -> ItemPreview {
         let mut information = String::from(format!("\x1b[1mDetails for {}\x1b[0m\n\n", self.id));
 
@@ -84,14 +384,35 @@ impl SkimItem for History {
 
         tformat("Runtime", &self.format_duration());
         tformat("Host", &self.host);
+        if get_histdb_databases().len() > 1 {
+            tformat("Database", &get_database_label(self.source));
+        }
         tformat("Executed", &self.count.to_string());
+        if self.count > 1 {
+            tformat("First Run", &History::format_date(self.first_start, false));
+        }
+        if let Some(success_rate) = self.success_rate {
+            tformat("Success Rate", &format!("{:.0}%", success_rate * 100.0));
+        }
+        if let Some(frecency) = self.frecency {
+            tformat("Frecency", &format!("{:.2}", frecency));
+        }
         tformat("Directory", &self.dir);
+        if self.hosts.len() > 1 {
+            tformat("Hosts", &self.hosts.join(", "));
+        }
+        if self.dirs.len() > 1 {
+            tformat("Directories", &self.dirs.join(&format!("\n{:20}", "")));
+        }
         tformat("Exit Status", &History::format_or_none(self.exit_status));
         tformat("Session", &self.session.to_string());
-        tformat("Start Time", &self.format_date(false));
+        tformat("Start Time", &History::format_date(self.start, true));
+        if get_preview_neighbours() > 0 {
+            information.push_str(&self.format_neighbours(_context.width));
+        }
         information.push_str(&format!(
             "\x1b[1mCommand\x1b[0m\n\n{}\n",
-            &fill(&self.cmd, _context.width)
+            self.format_command(_context.width)
         ));
         ItemPreview::AnsiText(information)
     }
@@ -104,3 +425,113 @@ impl SkimItem for History {
         Cow::Borrowed(&self.cmd)
     }
 }
+
+/// Patch the styles onto the unmatched parts of the line, the ranges are in chars
+fn apply_styles<'a>(
+    line: Line<'a>,
+    styles: &[(usize, usize, Style)],
+    base_style: Style,
+) -> Line<'a> {
+    let mut styled = Line::default();
+    let mut position = 0;
+    for span in line.spans {
+        let mut piece = String::new();
+        let mut piece_style = None;
+        for c in span.content.chars() {
+            let style = match styles
+                .iter()
+                .find(|(start, end, _)| (*start..*end).contains(&position))
+            {
+                Some((_, _, style)) if span.style == base_style => base_style.patch(*style),
+                _ => span.style,
+            };
+            if piece_style.is_some_and(|s| s != style) {
+                styled.push_span(Span::styled(
+                    std::mem::take(&mut piece),
+                    piece_style.unwrap(),
+                ));
+            }
+            piece.push(c);
+            piece_style = Some(style);
+            position += 1;
+        }
+        if let Some(style) = piece_style {
+            styled.push_span(Span::styled(piece, style));
+        }
+    }
+    styled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_age() {
+        let now = 1_700_000_000;
+        assert_eq!(History::format_age(now, now), "0s ago");
+        assert_eq!(History::format_age(now - 42, now), "42s ago");
+        assert_eq!(History::format_age(now - 5 * 60, now), "5m ago");
+        assert_eq!(History::format_age(now - 3 * 3600 - 59, now), "3h ago");
+        assert_eq!(History::format_age(now - 2 * 86400, now), "2d ago");
+        assert_eq!(History::format_age(now - 15 * 86400, now), "2w ago");
+        assert_eq!(History::format_age(now - 364 * 86400, now), "11mo ago");
+        assert_eq!(History::format_age(now - 800 * 86400, now), "2y ago");
+        assert_eq!(History::format_age(now + 10, now), "0s ago");
+    }
+
+    #[test]
+    fn formats_compact_duration() {
+        assert_eq!(History::format_compact_duration(0), "0s");
+        assert_eq!(History::format_compact_duration(90), "1m30s");
+        assert_eq!(History::format_compact_duration(3599), "59m59s");
+        assert_eq!(History::format_compact_duration(3900), "1h5m");
+        assert_eq!(History::format_compact_duration(2 * 86400 + 7200), "2d2h");
+    }
+
+    #[test]
+    fn flattens_multi_line_commands() {
+        assert_eq!(
+            History::flatten("for f in *; do\n    echo   $f\n\ndone"),
+            "for f in *; do ↵ echo $f ↵ done"
+        );
+        assert_eq!(
+            History::flatten("docker run \\\n  --rm \\\n  alpine"),
+            "docker run --rm alpine"
+        );
+        assert_eq!(History::flatten("  ls\t-la  "), "ls -la");
+    }
+
+    #[test]
+    fn cuts_long_commands_at_a_word() {
+        let flat = History::flatten(&"echo abc ".repeat(100));
+        assert!(flat.chars().count() <= History::MAX_LIST_LENGTH + 1);
+        assert!(
+            flat.ends_with("abc…") || flat.ends_with("echo…"),
+            "{}",
+            flat
+        );
+    }
+
+    #[test]
+    fn styles_unmatched_parts() {
+        let base = Style::default();
+        let matched = Style::default().fg(Color::Yellow);
+        let green = Style::default().fg(Color::Green);
+        let line = Line::from(vec![
+            Span::styled("✓ echo ", base),
+            Span::styled("o", matched),
+            Span::styled("k", base),
+        ]);
+        let styled = apply_styles(line, &[(0, 1, green), (7, 9, green)], base);
+        let spans: Vec<(&str, Style)> = styled
+            .spans
+            .iter()
+            .map(|span| (span.content.as_ref(), span.style))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![("✓", green), (" echo ", base), ("o", matched), ("k", green)]
+        );
+    }
+}
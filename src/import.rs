@@ -0,0 +1,428 @@
+use crate::database::{entry_exists, insert_entry, open_existing, open_for_import, unused_session};
+use crate::environment::*;
+use clap::ValueEnum;
+use rusqlite::Connection;
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Directory of imported entries, history files don't record where a command ran
+pub const IMPORT_DIR_PLACEHOLDER: &str = "(imported)";
+
+/// Marks a metafied byte in zsh's history file, the next byte is xored with 32
+const ZSH_META: u8 = 0x83;
+
+/// A command read from a shell's history file
+#[derive(PartialEq, Eq, Debug)]
+pub struct ImportEntry {
+    pub cmd: String,
+    pub start: i64,
+    pub duration: Option<i64>,
+}
+
+/// Which entries already in the database an imported one is skipped for, by default the
+/// ones at the same time
+#[derive(PartialEq, Eq, Debug, ValueEnum, Copy, Clone, Default)]
+pub enum Dedup {
+    /// The same command at the same time on the host
+    #[default]
+    Time,
+    /// The same command at any time on the host
+    Command,
+    /// Import every entry
+    Off,
+}
+
+/// Where the imported entries are put and how
+pub struct ImportOptions {
+    pub host: Option<String>,
+    pub dir: Option<String>,
+    pub session: Option<i64>,
+    pub dedup: Option<Dedup>,
+    pub dry_run: bool,
+}
+
+/// Import `~/.zsh_history` or another zsh history file into the database
+pub fn import_zsh_history(file: Option<PathBuf>, options: ImportOptions) -> Result<(), String> {
+    let path = history_file(file, ".zsh_history")?;
+    let bytes = fs::read(&path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let (entries, skipped) = parse_zsh_history(&bytes);
+    import_entries(&entries, &path, &get_histdb_database(), options)?;
+    if skipped > 0 {
+        println!("Skipped {} lines without a timestamp", skipped);
+    }
+    Ok(())
+}
+
+/// Import `~/.bash_history` or another bash history file into the database
+pub fn import_bash_history(
+    file: Option<PathBuf>,
+    mut options: ImportOptions,
+) -> Result<(), String> {
+    let path = history_file(file, ".bash_history")?;
+    let bytes = fs::read(&path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let modified = fs::metadata(&path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|age| age.as_secs() as i64)
+        .unwrap_or_default();
+    let text = String::from_utf8_lossy(&bytes);
+    // the made up times change as the file grows, so only the commands can be compared
+    if !text.lines().any(|line| bash_timestamp(line).is_some()) {
+        options.dedup = options.dedup.or(Some(Dedup::Command));
+    }
+    let entries = parse_bash_history(&text, modified);
+    import_entries(&entries, &path, &get_histdb_database(), options)
+}
+
+/// The given history file or the one in the home directory
+fn history_file(file: Option<PathBuf>, name: &str) -> Result<PathBuf, String> {
+    match file {
+        Some(file) => Ok(file),
+        None => env::var("HOME")
+            .map(|home| PathBuf::from(home).join(name))
+            .map_err(|_| format!("HOME is not set, give the path of {}", name)),
+    }
+}
+
+/// Write the entries into the database, or only count them for a dry run. A dry run
+/// opens the database read-only and doesn't create it
+fn import_entries(
+    entries: &[ImportEntry],
+    path: &Path,
+    database: &str,
+    options: ImportOptions,
+) -> Result<(), String> {
+    let host = options
+        .host
+        .or_else(|| Some(get_current_host()).filter(|host| !host.is_empty()))
+        .ok_or("No host, set HISTDB_HOST or use --host")?;
+    let dir = options
+        .dir
+        .unwrap_or_else(|| IMPORT_DIR_PLACEHOLDER.to_string());
+    let db_error = |e: rusqlite::Error| format!("Could not import into database: {}", e);
+    let mut conn = if options.dry_run {
+        open_existing(database.to_string()).map_err(db_error)?
+    } else {
+        Some(open_for_import(database.to_string()).map_err(db_error)?)
+    };
+    let session = match (options.session, &conn) {
+        (Some(session), _) => session,
+        (None, Some(conn)) => unused_session(conn).map_err(db_error)?,
+        // without a database every session is unused
+        (None, None) => 1,
+    };
+    let dedup = options.dedup.unwrap_or_default();
+    let new_entries = new_entries(entries, conn.as_ref(), &host, dedup).map_err(db_error)?;
+    match conn.as_mut().filter(|_| !options.dry_run) {
+        Some(conn) => {
+            let tx = conn.transaction().map_err(db_error)?;
+            for entry in &new_entries {
+                insert_entry(
+                    &tx,
+                    &entry.cmd,
+                    &host,
+                    &dir,
+                    session,
+                    entry.start,
+                    entry.duration,
+                )
+                .map_err(db_error)?;
+            }
+            tx.commit().map_err(db_error)?;
+            print!("Imported");
+        }
+        None => print!("Would import"),
+    }
+    println!(
+        " {} of {} commands from {} into session {}, {} already in the database",
+        new_entries.len(),
+        entries.len(),
+        path.display(),
+        session,
+        entries.len() - new_entries.len()
+    );
+    Ok(())
+}
+
+/// The entries that are not in the database yet, without the database all of them apart
+/// from repeats within the entries
+fn new_entries<'a>(
+    entries: &'a [ImportEntry],
+    conn: Option<&Connection>,
+    host: &str,
+    dedup: Dedup,
+) -> rusqlite::Result<Vec<&'a ImportEntry>> {
+    let mut seen = HashSet::new();
+    let mut new_entries = Vec::new();
+    for entry in entries {
+        let start = match dedup {
+            Dedup::Time => Some(entry.start),
+            Dedup::Command => None,
+            Dedup::Off => {
+                new_entries.push(entry);
+                continue;
+            }
+        };
+        if !seen.insert((entry.cmd.as_str(), start)) {
+            continue;
+        }
+        let exists = match conn {
+            Some(conn) => entry_exists(conn, &entry.cmd, host, start)?,
+            None => false,
+        };
+        if !exists {
+            new_entries.push(entry);
+        }
+    }
+    Ok(new_entries)
+}
+
+/// The time of a `#<epoch>` line of bash's history file
+fn bash_timestamp(line: &str) -> Option<i64> {
+    line.strip_prefix('#')
+        .filter(|epoch| !epoch.is_empty() && epoch.bytes().all(|b| b.is_ascii_digit()))
+        .and_then(|epoch| epoch.parse::<i64>().ok())
+}
+
+/// Parse bash's history file. With `HISTTIMEFORMAT` set, `#<epoch>` lines precede the
+/// commands and all lines up to the next one belong to the command. Lines without them,
+/// the whole file or the ones written before `HISTTIMEFORMAT` was set, are one command
+/// each, given increasing times ending at `modified` or the first timestamp to keep their order
+pub fn parse_bash_history(text: &str, modified: i64) -> Vec<ImportEntry> {
+    let first_start = match text.lines().find_map(bash_timestamp) {
+        Some(start) => start,
+        None => return untimed_entries(text.lines(), modified),
+    };
+    let mut entries = untimed_entries(
+        text.lines()
+            .take_while(|line| bash_timestamp(line).is_none()),
+        first_start,
+    );
+    let mut current: Option<(i64, Vec<&str>)> = None;
+    for line in text.lines() {
+        if let Some(start) = bash_timestamp(line) {
+            entries.extend(current.take().and_then(bash_entry));
+            current = Some((start, Vec::new()));
+        } else if let Some((_, lines)) = &mut current {
+            lines.push(line);
+        }
+    }
+    entries.extend(current.and_then(bash_entry));
+    entries
+}
+
+/// A command for each non-empty line, with increasing times ending before `end`
+fn untimed_entries<'a>(lines: impl Iterator<Item = &'a str>, end: i64) -> Vec<ImportEntry> {
+    let lines: Vec<&str> = lines.filter(|line| !line.trim().is_empty()).collect();
+    lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| ImportEntry {
+            cmd: line.to_string(),
+            start: end - (lines.len() - i) as i64,
+            duration: None,
+        })
+        .collect()
+}
+
+/// The command of the lines after a timestamp, unless they are empty
+fn bash_entry((start, lines): (i64, Vec<&str>)) -> Option<ImportEntry> {
+    let cmd = lines.join("\n");
+    if cmd.trim().is_empty() {
+        return None;
+    }
+    Some(ImportEntry {
+        cmd,
+        start,
+        duration: None,
+    })
+}
+
+/// Undo zsh's metafication of bytes it uses internally
+fn unmetafy(bytes: &[u8]) -> Vec<u8> {
+    let mut plain = Vec::with_capacity(bytes.len());
+    let mut iter = bytes.iter();
+    while let Some(&byte) = iter.next() {
+        if byte == ZSH_META {
+            if let Some(&next) = iter.next() {
+                plain.push(next ^ 32);
+            }
+        } else {
+            plain.push(byte);
+        }
+    }
+    plain
+}
+
+/// Parse zsh's extended history format, `: <start>:<duration>;<command>` with lines
+/// of multi-line commands ending in a backslash. Returns the entries and the number
+/// of lines without a timestamp
+pub fn parse_zsh_history(bytes: &[u8]) -> (Vec<ImportEntry>, usize) {
+    let text = String::from_utf8_lossy(&unmetafy(bytes)).into_owned();
+    let mut entries = Vec::new();
+    let mut skipped = 0;
+    let mut lines = text.lines();
+    while let Some(line) = lines.next() {
+        let mut cmd = String::new();
+        let mut current = line;
+        while let Some(continued) = current.strip_suffix('\\') {
+            cmd.push_str(continued);
+            cmd.push('\n');
+            match lines.next() {
+                Some(next) => current = next,
+                None => {
+                    current = "";
+                    break;
+                }
+            }
+        }
+        cmd.push_str(current);
+        match parse_zsh_header(&cmd) {
+            Some((start, duration, command)) if !command.trim().is_empty() => {
+                entries.push(ImportEntry {
+                    cmd: command.to_string(),
+                    start,
+                    duration: Some(duration),
+                });
+            }
+            Some(_) => {}
+            None => skipped += 1,
+        }
+    }
+    (entries, skipped)
+}
+
+/// Split `: <start>:<duration>;<command>` into its parts
+fn parse_zsh_header(line: &str) -> Option<(i64, i64, &str)> {
+    let rest = line.strip_prefix(": ")?;
+    let (times, command) = rest.split_once(';')?;
+    let (start, duration) = times.split_once(':')?;
+    Some((
+        start.trim().parse().ok()?,
+        duration.trim().parse().ok()?,
+        command,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(cmd: &str, start: i64, duration: i64) -> ImportEntry {
+        ImportEntry {
+            cmd: cmd.to_string(),
+            start,
+            duration: Some(duration),
+        }
+    }
+
+    fn options(dry_run: bool) -> ImportOptions {
+        ImportOptions {
+            host: Some("box".to_string()),
+            dir: None,
+            session: None,
+            dedup: Some(Dedup::Time),
+            dry_run,
+        }
+    }
+
+    #[test]
+    fn dry_run_leaves_disk_alone() {
+        let database = env::temp_dir().join(format!("histdb-dry-run-{}.db", std::process::id()));
+        let _ = fs::remove_file(&database);
+        let entries = vec![entry("ls", 1, 0), entry("ls", 1, 0), entry("make", 2, 0)];
+        let database_path = database.to_str().unwrap();
+        import_entries(&entries, Path::new("history"), database_path, options(true)).unwrap();
+        assert!(!database.exists());
+
+        import_entries(
+            &entries,
+            Path::new("history"),
+            database_path,
+            options(false),
+        )
+        .unwrap();
+        let conn = open_existing(database_path.to_string()).unwrap().unwrap();
+        let count: i64 = conn
+            .query_row("select count(*) from history", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 2);
+        assert!(new_entries(&entries, Some(&conn), "box", Dedup::Time)
+            .unwrap()
+            .is_empty());
+        fs::remove_file(&database).unwrap();
+    }
+
+    #[test]
+    fn parses_extended_history() {
+        let history =
+            b": 1700000000:0;ls -la\n: 1700000005:12;for f in *; do\\\n  echo $f\\\ndone\nplain\n";
+        assert_eq!(
+            parse_zsh_history(history),
+            (
+                vec![
+                    entry("ls -la", 1700000000, 0),
+                    entry("for f in *; do\n  echo $f\ndone", 1700000005, 12),
+                ],
+                1
+            )
+        );
+    }
+
+    #[test]
+    fn unmetafies_bytes() {
+        // "ä" is 0xc3 0xa4, zsh writes 0xa4 as Meta followed by 0xa4 ^ 32
+        let history = [b": 1:0;echo \xc3".as_slice(), &[ZSH_META, 0xa4 ^ 32], b"\n"].concat();
+        assert_eq!(parse_zsh_history(&history).0, vec![entry("echo ä", 1, 0)]);
+    }
+
+    #[test]
+    fn parses_bash_history_with_timestamps() {
+        let history =
+            "#1700000000\nls -la\n#1700000005\nfor f in *; do\n  echo $f\ndone\n#1700000009\n";
+        let entries = parse_bash_history(history, 0);
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| (entry.cmd.as_str(), entry.start))
+                .collect::<Vec<_>>(),
+            vec![
+                ("ls -la", 1700000000),
+                ("for f in *; do\n  echo $f\ndone", 1700000005)
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_bash_history_before_first_timestamp() {
+        let history = "ls\n\ncd /tmp\n#1700000000\nmake\n";
+        let entries = parse_bash_history(history, 0);
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| (entry.cmd.as_str(), entry.start))
+                .collect::<Vec<_>>(),
+            vec![
+                ("ls", 1699999998),
+                ("cd /tmp", 1699999999),
+                ("make", 1700000000)
+            ]
+        );
+    }
+
+    #[test]
+    fn orders_bash_history_without_timestamps() {
+        let entries = parse_bash_history("ls\n\n# a comment\ngit status\n", 100);
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| (entry.cmd.as_str(), entry.start))
+                .collect::<Vec<_>>(),
+            vec![("ls", 97), ("# a comment", 98), ("git status", 99)]
+        );
+    }
+}
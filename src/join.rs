@@ -0,0 +1,49 @@
+use crate::history::History;
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// How several selected commands are combined into one command line
+#[derive(PartialEq, Eq, Debug, ValueEnum, Deserialize, Copy, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Join {
+    /// One command per line
+    #[default]
+    Newline,
+    /// Run the next command only if the previous one succeeded
+    And,
+}
+
+/// Join the commands in the order they were executed
+pub fn join_commands(mut entries: Vec<&History>, join: Join) -> String {
+    entries.sort_by_key(|entry| (entry.start, entry.id));
+    let separator = match join {
+        Join::Newline => "\n",
+        Join::And => " && ",
+    };
+    entries
+        .iter()
+        .map(|entry| entry.command().as_str())
+        .collect::<Vec<&str>>()
+        .join(separator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_in_execution_order() {
+        let make = History::example(3, "make", 200);
+        let ls = History::example(1, "ls", 100);
+        let cd = History::example(2, "cd /tmp", 100);
+        let test = History::example(4, "make test", 200);
+        assert_eq!(
+            join_commands(vec![&make, &test, &cd, &ls], Join::Newline),
+            "ls\ncd /tmp\nmake\nmake test"
+        );
+        assert_eq!(
+            join_commands(vec![&test, &make], Join::And),
+            "make && make test"
+        );
+    }
+}
@@ -1,9 +1,14 @@
+use clap::ValueEnum;
 use enum_map::Enum;
+use serde::Deserialize;
 
-#[derive(PartialEq, Enum, Copy, Clone)]
+#[derive(PartialEq, Eq, Hash, Debug, Enum, ValueEnum, Deserialize, Copy, Clone)]
+#[serde(rename_all = "kebab-case")]
 pub enum Location {
     Session,
     Directory,
+    DirectoryTree,
+    Repository,
     Machine,
     Everywhere,
 }
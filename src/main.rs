@@ -1,22 +1,50 @@
 extern crate skim;
+mod action;
+mod app_state;
+mod clipboard;
+mod column;
+mod config;
+mod database;
+mod date_format;
 mod environment;
+mod exit_filter;
+mod export;
+mod highlight;
 mod history;
+mod import;
+mod join;
 mod location;
 mod query;
+mod search;
+mod sort;
+mod time_range;
 mod title;
 
+use crate::action::Action;
+use crate::app_state::AppState;
+use crate::clipboard::copy_to_clipboard;
+use crate::config::{config, load_config};
+use crate::database::{delete_command, delete_entry, merge_database, open_writable, read_history};
 use crate::environment::*;
+use crate::exit_filter::ExitFilter;
+use crate::export::{export, ExportFormat};
 use crate::history::History;
+use crate::import::{import_bash_history, import_zsh_history, Dedup, ImportOptions};
+use crate::join::{join_commands, Join};
 use crate::location::Location;
 use crate::query::build_query_string;
+use crate::search::{search, OutputFormat};
+use crate::sort::SortMode;
+use crate::time_range::{parse_time, TimeRange};
 use crate::title::generate_title;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use color_eyre::{Report, Result};
 use crossterm::event::{KeyCode, KeyModifiers};
 use rusqlite::{Connection, OpenFlags};
 use skim::prelude::*;
-use std::collections::HashSet;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 use std::thread;
 
@@ -34,44 +62,23 @@ impl HistoryCollection {
     }
 }
 
-fn read_entries(history_collection: Arc<Mutex<HistoryCollection>>) {
-    let conn_res =
-        Connection::open_with_flags(get_histdb_database(), OpenFlags::SQLITE_OPEN_READ_ONLY);
-    if conn_res.is_err() {
-        return;
-    }
-    let conn = conn_res.unwrap();
+/// Collections already read from the database, per location and grouping
+type HistoryCache = HashMap<(Location, bool), Arc<Mutex<HistoryCollection>>>;
 
-    let s = build_query_string();
-
-    let stmt_result = conn.prepare(&s);
-    if stmt_result.is_err() {
-        return;
-    }
-    let mut stmt = stmt_result.unwrap();
-
-    let history_entries = stmt
-        .query_map([], |row| {
-            let cmd: String = row.get("cmd")?;
-            let start: i64 = row.get("start")?;
-            let commandend = cmd.len();
-            Ok(History {
-                id: row.get("id")?,
-                cmd,
-                start: start as u64,
-                exit_status: row.get("exit_status")?,
-                duration: row.get("duration")?,
-                count: row.get("count")?,
-                session: row.get("session")?,
-                host: row.get("host")?,
-                dir: row.get("dir")?,
-                searchrange: [(
-                    History::COMMAND_START,
-                    commandend + (History::COMMAND_START),
-                )],
-            })
-        })
-        .unwrap();
+fn read_entries(
+    history_collection: Arc<Mutex<HistoryCollection>>,
+    location: &Location,
+    grouped: bool,
+    app_state: &AppState,
+) {
+    let s = build_query_string(location, grouped, app_state);
+    let history_entries = match read_history(&s, app_state.sort) {
+        Ok(history_entries) => history_entries,
+        Err(_) => {
+            history_collection.lock().unwrap().filled = true;
+            return;
+        }
+    };
 
     let mut filtered_history_entries = history_entries.filter_map(|x| x.ok()).peekable();
 
@@ -90,36 +97,34 @@ fn read_entries(history_collection: Arc<Mutex<HistoryCollection>>) {
     c.filled = true;
 }
 
-fn filter_entry(location: &Location, app_state: &AppState, entry: &History) -> bool {
-    match location {
-        Location::Session => entry.session == app_state.session && entry.host == app_state.machine,
-        Location::Directory => entry.dir == app_state.dir && entry.host == app_state.machine,
-        Location::Machine => entry.host == app_state.machine,
-        Location::Everywhere => true,
-    }
-}
-
-struct AppState {
-    session: i64,
-    dir: String,
-    machine: String,
+/// Get the collection for the location, starting to read it if it is not cached yet
+fn get_entries(
+    cache: &mut HistoryCache,
+    location: &Location,
+    grouped: bool,
+    app_state: &AppState,
+) -> Arc<Mutex<HistoryCollection>> {
+    let collection = cache.entry((*location, grouped)).or_insert_with(|| {
+        let history_collection = Arc::new(Mutex::new(HistoryCollection::new()));
+        let location = *location;
+        let app_state = app_state.clone();
+        {
+            let history_collection = history_collection.clone();
+            thread::spawn(move || {
+                read_entries(history_collection, &location, grouped, &app_state);
+            });
+        }
+        history_collection
+    });
+    collection.clone()
 }
 
-fn filter_entries(
+fn send_entries(
     history_collection: Arc<Mutex<HistoryCollection>>,
-    location: &Location,
     tx_item: SkimItemSender,
     end_early: Arc<Mutex<bool>>,
-    grouped: bool,
+    relative_time: bool,
 ) {
-    let app_state = AppState {
-        session: get_current_session_id().parse::<i64>().unwrap(),
-        dir: get_current_dir(),
-        machine: get_current_host(),
-    };
-
-    // TODO: Do we care about grouping across hosts?
-    let mut seen_commands = HashSet::new();
     let mut filled = false;
     let mut len = 0;
     let mut next_idx = 0;
@@ -133,25 +138,22 @@ fn filter_entries(
             }
         }
         // try to grab up to block_size items
-        let mut entries_block: Vec<Arc<dyn SkimItem>> = Vec::with_capacity(block_size);
         let c = history_collection.lock().unwrap();
         filled = c.filled;
         len = c.collection.len();
 
-        for i in next_idx..len {
-            if (!grouped || !seen_commands.contains(&c.collection[i].cmd))
-                && filter_entry(location, &app_state, &c.collection[i])
-            {
-                entries_block.push(Arc::new(c.collection[i].clone()));
-                seen_commands.insert(c.collection[i].cmd.clone());
-            }
-            next_idx = i + 1;
-            if entries_block.len() == block_size {
-                break;
-            }
-        }
+        let block_end = len.min(next_idx + block_size);
+        let entries_block: Vec<Arc<dyn SkimItem>> = c.collection[next_idx..block_end]
+            .iter()
+            .map(|entry| {
+                let mut entry = entry.clone();
+                entry.set_relative_time(relative_time);
+                Arc::new(entry) as Arc<dyn SkimItem>
+            })
+            .collect();
+        next_idx = block_end;
 
-        if entries_block.len() > 0 {
+        if !entries_block.is_empty() {
             let _ = tx_item.send(entries_block);
         }
     }
@@ -160,11 +162,98 @@ fn filter_entries(
 enum SelectionResult {
     Command(String),
     NullCommand,
+    Delete(Box<History>),
     Continue,
     Abort,
 }
 
+/// What to delete from the database
+enum Deletion {
+    Entry,
+    Command,
+}
+
+/// Ask whether to delete only the execution or all executions of the command
+fn confirm_deletion(entry: &History) -> Option<Deletion> {
+    let keep = "No, keep it";
+    let delete_entry = "Yes, delete this execution";
+    let delete_command = "Yes, delete all executions of this command";
+
+    let options = SkimOptionsBuilder::default()
+        .height("100%")
+        .reverse(true)
+        .prompt("Delete? ")
+        .header(format!("Delete from the history:\n{}\n", entry.command()))
+        .no_sort(true)
+        .build()
+        .unwrap();
+
+    let (tx_item, rx_item): (SkimItemSender, SkimItemReceiver) = unbounded();
+    let choices: Vec<Arc<dyn SkimItem>> = vec![
+        Arc::new(keep.to_string()),
+        Arc::new(delete_entry.to_string()),
+        Arc::new(delete_command.to_string()),
+    ];
+    let _ = tx_item.send(choices);
+    drop(tx_item);
+
+    let output = Skim::run_with(options, Some(rx_item)).ok()?;
+    if output.is_abort {
+        return None;
+    }
+    let choice = output.selected_items.first()?.output().to_string();
+    if choice == delete_entry {
+        Some(Deletion::Entry)
+    } else if choice == delete_command {
+        Some(Deletion::Command)
+    } else {
+        None
+    }
+}
+
+/// Delete the entry from the database, then from the collections read so far. Collections
+/// still being read are dropped and read again, they may have missed the deletion
+fn delete_history(entry: &History, cache: &mut HistoryCache) -> Result<(), String> {
+    // the other databases are read-only copies, only the main one is changed
+    if entry.source != 0 {
+        return Err(format!(
+            "The entry is in the read-only database {}",
+            get_database_label(entry.source)
+        ));
+    }
+    let deletion = match confirm_deletion(entry) {
+        Some(deletion) => deletion,
+        None => return Ok(()),
+    };
+    let mut conn = open_writable().map_err(|e| format!("Could not open database: {}", e))?;
+    match deletion {
+        Deletion::Entry => {
+            delete_entry(&mut conn, entry.id)
+                .map_err(|e| format!("Could not delete entry: {}", e))?;
+            // grouped entries summarize several executions, so they are read again
+            cache.retain(|(_, grouped), collection| !grouped && collection.lock().unwrap().filled);
+            for collection in cache.values() {
+                let mut c = collection.lock().unwrap();
+                c.collection.retain(|e| e.source != 0 || e.id != entry.id);
+            }
+        }
+        Deletion::Command => {
+            delete_command(&mut conn, entry.command())
+                .map_err(|e| format!("Could not delete command: {}", e))?;
+            cache.retain(|_, collection| collection.lock().unwrap().filled);
+            for collection in cache.values() {
+                let mut c = collection.lock().unwrap();
+                c.collection.retain(|e| e.source != 0 || e.cmd != entry.cmd);
+            }
+        }
+    }
+    Ok(())
+}
+
 fn get_starting_location() -> Location {
+    if let Some(location) = config().location {
+        return location;
+    }
     let mut location = Location::Session;
     if get_current_session_id().is_empty() {
         location = Location::Directory;
@@ -172,41 +261,50 @@ fn get_starting_location() -> Location {
     location
 }
 
-fn show_history(thequery: String) -> Result<String, String> {
+/// Show the history, with `join` set several commands can be selected
+fn show_history(
+    thequery: String,
+    mut app_state: AppState,
+    join: Option<Join>,
+) -> Result<String, String> {
     let mut location = get_starting_location();
-    let mut grouped = true;
+    let mut grouped = config().group.unwrap_or(true);
+    let mut relative_time = config().relative_time.unwrap_or(false);
     let mut query = thequery;
-    let history_collection = Arc::new(Mutex::new(HistoryCollection::new()));
-
-    let _handle = {
-        let history_collection = history_collection.clone();
-        thread::spawn(move || {
-            read_entries(history_collection);
-        })
-    };
+    let mut cache = HistoryCache::new();
+    // shown below the title on the next run, e.g. why a deletion failed
+    let mut message: Option<String> = None;
 
     loop {
-        let title = generate_title(&location);
+        let mut title = generate_title(&location, &app_state);
+        if let Some(message) = message.take() {
+            title = format!("{}{}\n", title, message);
+        }
 
-        let options = SkimOptionsBuilder::default()
+        let mut binds: Vec<String> = config()
+            .keys
+            .all()
+            .iter()
+            .map(|(_, key)| format!("{}:abort", key))
+            .collect();
+        binds.push("ctrl-u:half-page-up".into());
+        binds.push("ctrl-d:half-page-down".into());
+
+        let mut options = SkimOptionsBuilder::default();
+        if let Some(color) = &config().color {
+            options.color(color.clone());
+        }
+        let options = options
             .height("100%")
-            .multi(false)
+            .multi(join.is_some())
             .reverse(true)
             .prompt(">")
             .query(&query)
-            .bind(vec![
-                "f1:abort".into(),
-                "f2:abort".into(),
-                "f3:abort".into(),
-                "f4:abort".into(),
-                "f5:abort".into(),
-                "ctrl-r:abort".into(),
-                "ctrl-u:half-page-up".into(),
-                "ctrl-d:half-page-down".into(),
-            ])
+            .bind(binds)
             .header(&title)
             .preview("") // preview should be specified to enable preview window
-            .no_sort(get_nosort_option())
+            .preview_window(config().preview.as_deref().unwrap_or("right:50%"))
+            .no_sort(get_nosort_option() || app_state.sort == SortMode::Frecency)
             .build()
             .unwrap();
 
@@ -214,10 +312,10 @@ fn show_history(thequery: String) -> Result<String, String> {
         let end_early = Arc::new(Mutex::new(false));
 
         let handle = {
-            let history_collection = history_collection.clone();
+            let history_collection = get_entries(&mut cache, &location, grouped, &app_state);
             let end_early = end_early.clone();
             thread::spawn(move || {
-                filter_entries(history_collection, &location, tx_item, end_early, grouped);
+                send_entries(history_collection, tx_item, end_early, relative_time);
             })
         };
 
@@ -231,13 +329,30 @@ fn show_history(thequery: String) -> Result<String, String> {
         }
         handle.join().unwrap();
 
-        let selection_result = process_result(&selected_items, &mut location, &mut grouped);
+        let previous_state = app_state.clone();
+        let selection_result = process_result(
+            &selected_items,
+            &mut location,
+            &mut grouped,
+            &mut relative_time,
+            &mut app_state,
+            join,
+        );
+        if app_state != previous_state {
+            cache.clear();
+        }
 
         match selection_result {
             SelectionResult::Abort => return Err("Aborted".to_string()),
             SelectionResult::Continue => query = selected_items.unwrap().query,
             SelectionResult::Command(command) => return Ok(command),
             SelectionResult::NullCommand => return Ok(selected_items.unwrap().query),
+            SelectionResult::Delete(entry) => {
+                if let Err(e) = delete_history(&entry, &mut cache) {
+                    message = Some(e);
+                }
+                query = selected_items.unwrap().query;
+            }
         };
     }
 }
@@ -246,6 +361,9 @@ fn process_result(
     selected_items: &Result<SkimOutput, Report>,
     loc: &mut Location,
     grouped: &mut bool,
+    relative_time: &mut bool,
+    app_state: &mut AppState,
+    join: Option<Join>,
 ) -> SelectionResult {
     if selected_items.is_ok() {
         let sel = selected_items.as_ref().unwrap();
@@ -259,35 +377,72 @@ fn process_result(
             (KeyCode::Enter, KeyModifiers::NONE) => {
                 if sel.selected_items.is_empty() {
                     return SelectionResult::NullCommand;
+                } else if let Some(join) = join {
+                    let entries = sel
+                        .selected_items
+                        .iter()
+                        .filter_map(|item| (*item.item).as_any().downcast_ref::<History>())
+                        .collect();
+                    return SelectionResult::Command(join_commands(entries, join));
                 } else {
                     return SelectionResult::Command(sel.selected_items[0].output().to_string());
                 }
             }
-            (KeyCode::F(1), KeyModifiers::NONE) => {
-                *loc = Location::Session;
-            }
-            (KeyCode::F(2), KeyModifiers::NONE) => {
-                *loc = Location::Directory;
-            }
-            (KeyCode::F(3), KeyModifiers::NONE) => {
-                *loc = Location::Machine;
+            _ => (),
+        };
+
+        let selected_entry = || {
+            sel.selected_items
+                .first()
+                .and_then(|item| (*item.item).as_any().downcast_ref::<History>())
+        };
+        match config().keys.action(&sel.final_key) {
+            Some(Action::Session) => *loc = Location::Session,
+            Some(Action::Directory) => *loc = Location::Directory,
+            Some(Action::DirectoryTree) => *loc = Location::DirectoryTree,
+            Some(Action::Repository) => *loc = Location::Repository,
+            Some(Action::Machine) => *loc = Location::Machine,
+            Some(Action::Everywhere) => *loc = Location::Everywhere,
+            Some(Action::ToggleGroup) => *grouped = !*grouped,
+            Some(Action::ToggleRelativeTime) => *relative_time = !*relative_time,
+            Some(Action::ToggleSort) => {
+                app_state.sort = match app_state.sort {
+                    SortMode::Recent => SortMode::Frecency,
+                    SortMode::Frecency => SortMode::Recent,
+                };
             }
-            (KeyCode::F(4), KeyModifiers::NONE) => {
-                *loc = Location::Everywhere;
+            Some(Action::CycleExitStatus) => {
+                app_state.exit_filter = match app_state.exit_filter {
+                    ExitFilter::All => ExitFilter::Success,
+                    ExitFilter::Success => ExitFilter::Failed,
+                    ExitFilter::Failed => ExitFilter::All,
+                };
             }
-            (KeyCode::F(5), KeyModifiers::NONE) => {
-                *grouped = !*grouped;
+            Some(Action::CycleTimeRange) => {
+                app_state.time_range = app_state.time_range.next(app_state.custom_time_range);
             }
-            (KeyCode::Char('r'), KeyModifiers::CONTROL) => {
+            Some(Action::CycleLocation) => {
                 *loc = match *loc {
                     Location::Session => Location::Directory,
-                    Location::Directory => Location::Machine,
+                    Location::Directory => Location::DirectoryTree,
+                    Location::DirectoryTree => Location::Repository,
+                    Location::Repository => Location::Machine,
                     Location::Machine => Location::Everywhere,
                     Location::Everywhere => Location::Session,
                 };
             }
-            _ => (),
-        };
+            Some(Action::Delete) => {
+                if let Some(entry) = selected_entry() {
+                    return SelectionResult::Delete(Box::new(entry.clone()));
+                }
+            }
+            Some(Action::Copy) => {
+                if let Some(entry) = selected_entry() {
+                    let _ = copy_to_clipboard(entry.command());
+                }
+            }
+            None => (),
+        }
         SelectionResult::Continue
     } else {
         SelectionResult::Continue
@@ -295,14 +450,258 @@ fn process_result(
 }
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, disable_help_subcommand = true)]
 struct Args {
     #[arg(long = "zsh", help = "ZSH source for plugin.")]
     zsh: bool,
-    #[arg(last = true)]
+    #[arg(
+        long = "bash",
+        conflicts_with = "zsh",
+        help = "Bash source for the key binding."
+    )]
+    bash: bool,
+    #[arg(
+        long = "fish",
+        conflicts_with_all = ["zsh", "bash"],
+        help = "Fish source for the key binding."
+    )]
+    fish: bool,
+    #[arg(
+        long = "sort",
+        global = true,
+        value_enum,
+        help = "Order of the entries, defaults to recent."
+    )]
+    sort: Option<SortMode>,
+    #[arg(
+        long = "exit-status",
+        global = true,
+        value_enum,
+        default_value_t,
+        help = "Show only commands with this exit status."
+    )]
+    exit_status: ExitFilter,
+    #[arg(
+        long = "since",
+        global = true,
+        value_parser = parse_time,
+        help = "Show only commands started since, e.g. 2h, 3days or 2024-01-31 12:00."
+    )]
+    since: Option<i64>,
+    #[arg(
+        long = "until",
+        global = true,
+        value_parser = parse_time,
+        help = "Show only commands started until, e.g. 2h, 3days or 2024-01-31 12:00."
+    )]
+    until: Option<i64>,
+    #[arg(long = "multi", help = "Select several commands with tab.")]
+    multi: bool,
+    #[arg(
+        long = "join",
+        value_enum,
+        help = "How several selected commands are joined, defaults to newline."
+    )]
+    join: Option<Join>,
+    // after `--`, so a command line like `export` is never run as a subcommand
+    #[arg(
+        last = true,
+        help = "The query the picker starts with, always after --."
+    )]
+    query: Vec<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the matching entries without the interactive picker
+    Search(SearchArgs),
+    /// Write every execution as JSON Lines or CSV, for backups and other tools
+    Export(ExportArgs),
+    /// Fold the history of another histdb database into this one
+    Merge(MergeArgs),
+    /// Import the history file of a shell into the database
+    #[command(subcommand)]
+    Import(ImportSource),
+}
+
+#[derive(Subcommand, Debug)]
+enum ImportSource {
+    /// Import zsh's history file in the extended format
+    ZshHistory(ImportArgs),
+    /// Import bash's history file, with the times of HISTTIMEFORMAT if recorded
+    BashHistory(ImportArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct ImportArgs {
+    #[arg(long = "host", help = "Host of the entries, defaults to HISTDB_HOST.")]
+    host: Option<String>,
+    #[arg(
+        long = "dir",
+        help = "Directory of the entries, defaults to a placeholder."
+    )]
+    dir: Option<String>,
+    #[arg(
+        long = "session",
+        help = "Session of the entries, defaults to a new one."
+    )]
+    session: Option<i64>,
+    #[arg(
+        long = "dedup",
+        value_enum,
+        help = "Skip entries already in the database, defaults to time, to command for bash files without times."
+    )]
+    dedup: Option<Dedup>,
+    #[arg(
+        long = "dry-run",
+        help = "Only count the entries that would be imported."
+    )]
+    dry_run: bool,
+    /// History file, defaults to the one in the home directory
+    file: Option<PathBuf>,
+}
+
+impl ImportArgs {
+    fn options(&self) -> ImportOptions {
+        ImportOptions {
+            host: self.host.clone(),
+            dir: self.dir.clone(),
+            session: self.session,
+            dedup: self.dedup,
+            dry_run: self.dry_run,
+        }
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct SearchArgs {
+    #[arg(
+        long = "location",
+        value_enum,
+        help = "Where the commands were run, defaults to the session or the directory, to the session with --session."
+    )]
+    location: Option<Location>,
+    #[arg(
+        long = "no-group",
+        help = "Show every execution instead of each command once."
+    )]
+    no_group: bool,
+    #[arg(long = "limit", help = "Print at most this many entries.")]
+    limit: Option<usize>,
+    #[command(flatten)]
+    scope: ScopeArgs,
+    #[arg(
+        long = "format",
+        value_enum,
+        default_value_t,
+        help = "How the entries are printed."
+    )]
+    format: OutputFormat,
+    /// Words all printed commands contain
     query: Vec<String>,
 }
 
+#[derive(clap::Args, Debug)]
+struct ExportArgs {
+    #[arg(
+        long = "location",
+        value_enum,
+        default_value = "everywhere",
+        help = "Where the commands were run."
+    )]
+    location: Location,
+    #[command(flatten)]
+    scope: ScopeArgs,
+    #[arg(
+        long = "format",
+        value_enum,
+        default_value_t,
+        help = "How the entries are written."
+    )]
+    format: ExportFormat,
+}
+
+#[derive(clap::Args, Debug)]
+struct MergeArgs {
+    /// Database to copy the entries from, it is left unchanged
+    file: PathBuf,
+}
+
+impl SearchArgs {
+    /// The given location, a given session is searched if there is none
+    fn location(&self) -> Location {
+        match (self.location, self.scope.session) {
+            (Some(location), _) => location,
+            (None, Some(_)) => Location::Session,
+            (None, None) => get_starting_location(),
+        }
+    }
+}
+
+/// The session, directory and host the locations are relative to
+#[derive(clap::Args, Debug)]
+struct ScopeArgs {
+    #[arg(long = "host", help = "Use this host instead of HISTDB_HOST.")]
+    host: Option<String>,
+    #[arg(long = "dir", help = "Use this directory instead of the current one.")]
+    dir: Option<String>,
+    #[arg(long = "session", help = "Use this session instead of HISTDB_SESSION.")]
+    session: Option<i64>,
+}
+
+impl ScopeArgs {
+    fn apply(self, mut app_state: AppState) -> AppState {
+        if let Some(host) = self.host {
+            app_state.machine = host;
+        }
+        if let Some(dir) = self.dir {
+            let dir = normalize_dir(&dir);
+            app_state.repository = get_repository_root(&dir);
+            app_state.dir = dir;
+        }
+        if let Some(session) = self.session {
+            app_state.session = session;
+        }
+        app_state
+    }
+}
+
+/// The directory as zsh-histdb records it, absolute and without a trailing slash. A
+/// directory that doesn't exist here, e.g. one of another host, is only trimmed
+fn normalize_dir(dir: &str) -> String {
+    match Path::new(dir).canonicalize() {
+        Ok(path) => path.to_string_lossy().to_string(),
+        Err(_) if dir.len() > 1 => dir.trim_end_matches('/').to_string(),
+        Err(_) => dir.to_string(),
+    }
+}
+
+/// Merge another database into the one of HISTDB_FILE and print what was added
+fn merge(file: &Path) -> Result<(), String> {
+    let other = file
+        .canonicalize()
+        .map_err(|e| format!("Could not open {}: {}", file.display(), e))?;
+    let database = get_histdb_database();
+    if Path::new(&database).canonicalize().ok().as_ref() == Some(&other) {
+        return Err(format!("{} is the database itself", file.display()));
+    }
+    let db_error = |e: rusqlite::Error| format!("Could not merge {}: {}", file.display(), e);
+    let mut conn = open_writable().map_err(db_error)?;
+    let summary = merge_database(&mut conn, &other.to_string_lossy()).map_err(db_error)?;
+    println!(
+        "Merged {} into {}: added {} entries, {} commands and {} places, skipped {} entries already there",
+        file.display(),
+        database,
+        summary.entries,
+        summary.commands,
+        summary.places,
+        summary.duplicates
+    );
+    Ok(())
+}
+
 fn zsh() {
     print!(
         r##"
@@ -332,10 +731,57 @@ bindkey -M viins '^r' histdb-skim-widget
     )
 }
 
-fn main() -> Result<()> {
-    let _conn =
-        Connection::open_with_flags(get_histdb_database(), OpenFlags::SQLITE_OPEN_READ_ONLY);
+fn bash() {
+    print!(
+        r##"
+_histdb_skim() {{
+  local origquery=$READLINE_LINE
+  local output
+  output=$( \
+    HISTDB_HOST=${{HISTDB_HOST:-"'$HOSTNAME'"}} \
+    HISTDB_SESSION=$HISTDB_SESSION \
+    HISTDB_FILE=$HISTDB_FILE \
+    zsh-histdb-skim -- "$origquery" \
+  )
+
+  if [ $? -eq 0 ]; then
+    READLINE_LINE=$output
+  else
+    READLINE_LINE=$origquery
+  fi
+
+  READLINE_POINT=${{#READLINE_LINE}}
+}}
+
+bind -m emacs-standard -x '"\C-r": _histdb_skim'
+bind -m vi-insert -x '"\C-r": _histdb_skim'
+"##
+    )
+}
 
+fn fish() {
+    print!(
+        r##"
+function histdb-skim-widget
+    set -l origquery (commandline | string collect)
+    set -l host "'$hostname'"
+    set -q HISTDB_HOST; and set host $HISTDB_HOST
+    set -l output (HISTDB_HOST=$host HISTDB_SESSION=$HISTDB_SESSION HISTDB_FILE=$HISTDB_FILE zsh-histdb-skim -- "$origquery")
+
+    if test $status -eq 0
+        commandline -r -- (string join \n -- $output; or echo)
+    end
+
+    commandline -f repaint
+end
+
+bind \cr histdb-skim-widget
+bind -M insert \cr histdb-skim-widget
+"##
+    )
+}
+
+fn main() -> Result<()> {
     let args = Args::parse();
 
     if args.zsh {
@@ -343,8 +789,97 @@ fn main() -> Result<()> {
         return Ok(());
     }
 
+    if args.bash {
+        bash();
+        return Ok(());
+    }
+
+    if args.fish {
+        fish();
+        return Ok(());
+    }
+
+    if let Err(e) = load_config() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+
+    let _conn =
+        Connection::open_with_flags(get_histdb_database(), OpenFlags::SQLITE_OPEN_READ_ONLY);
+
     let query = args.query.join(" ");
-    let result = show_history(query);
+    let custom_time_range = if args.since.is_some() || args.until.is_some() {
+        Some(TimeRange::Custom {
+            since: args.since,
+            until: args.until,
+        })
+    } else {
+        None
+    };
+    let app_state = AppState {
+        sort: args.sort.or(config().sort).unwrap_or_default(),
+        exit_filter: args.exit_status,
+        time_range: custom_time_range.unwrap_or(TimeRange::All),
+        custom_time_range,
+        ..AppState::from_environment()
+    };
+    if let Some(Command::Import(source)) = &args.command {
+        let result = match source {
+            ImportSource::ZshHistory(import_args) => {
+                import_zsh_history(import_args.file.clone(), import_args.options())
+            }
+            ImportSource::BashHistory(import_args) => {
+                import_bash_history(import_args.file.clone(), import_args.options())
+            }
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Merge(merge_args)) = &args.command {
+        if let Err(e) = merge(&merge_args.file) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Export(export_args)) = args.command {
+        let app_state = export_args.scope.apply(app_state);
+        if let Err(e) = export(&export_args.location, &app_state, export_args.format) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Search(search_args)) = args.command {
+        let location = search_args.location();
+        let app_state = search_args.scope.apply(app_state);
+        let result = search(
+            &location,
+            !search_args.no_group && config().group.unwrap_or(true),
+            &app_state,
+            &search_args.query.join(" "),
+            search_args.limit,
+            search_args.format,
+        );
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let join = if args.multi || config().multi.unwrap_or(false) {
+        Some(args.join.or(config().join).unwrap_or_default())
+    } else {
+        None
+    };
+    let result = show_history(query, app_state, join);
     if result.is_ok() {
         println!("{}", result.ok().unwrap());
     } else {
@@ -354,3 +889,91 @@ fn main() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn verifies_args() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn passes_subcommand_names_as_query() {
+        for word in ["search", "help", "export"] {
+            let args = Args::parse_from(["zsh-histdb-skim", "--", word]);
+            assert!(args.command.is_none());
+            assert_eq!(args.query, vec![word]);
+        }
+        assert!(Args::try_parse_from(["zsh-histdb-skim", "help"]).is_err());
+    }
+
+    #[test]
+    fn normalizes_dirs() {
+        assert_eq!(normalize_dir("/"), "/");
+        assert_eq!(
+            normalize_dir("/nonexistent/project/"),
+            "/nonexistent/project"
+        );
+        let current = std::env::current_dir().unwrap();
+        assert_eq!(normalize_dir("."), current.to_string_lossy());
+        assert_eq!(
+            normalize_dir(&format!("{}/", current.display())),
+            current.to_string_lossy()
+        );
+    }
+
+    #[test]
+    fn parses_search() {
+        let args = Args::parse_from([
+            "zsh-histdb-skim",
+            "search",
+            "--location",
+            "everywhere",
+            "--limit",
+            "5",
+            "--format",
+            "json",
+            "--since",
+            "2h",
+            "git",
+        ]);
+        assert!(args.since.is_some());
+        match args.command {
+            Some(Command::Search(search_args)) => {
+                assert_eq!(search_args.location, Some(Location::Everywhere));
+                assert_eq!(search_args.limit, Some(5));
+                assert_eq!(search_args.format, OutputFormat::Json);
+                assert_eq!(search_args.query, vec!["git"]);
+            }
+            _ => panic!("search not parsed"),
+        }
+    }
+
+    #[test]
+    fn searches_given_session() {
+        let args = Args::parse_from(["zsh-histdb-skim", "search", "--session", "42"]);
+        match args.command {
+            Some(Command::Search(search_args)) => {
+                assert_eq!(search_args.location(), Location::Session);
+            }
+            _ => panic!("search not parsed"),
+        }
+        let args = Args::parse_from([
+            "zsh-histdb-skim",
+            "search",
+            "--session",
+            "42",
+            "--location",
+            "machine",
+        ]);
+        match args.command {
+            Some(Command::Search(search_args)) => {
+                assert_eq!(search_args.location(), Location::Machine);
+            }
+            _ => panic!("search not parsed"),
+        }
+    }
+}
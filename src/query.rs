@@ -1,30 +1,217 @@
-pub fn build_query_string() -> String {
-    let mut query = String::from("select history.id as id, commands.argv as cmd,");
-    query.push_str(" start_time");
-    query.push_str(" as start, exit_status, duration,");
-    query.push_str(" 1");
-    query.push_str(" as count, history.session as session, places.host as host, places.dir as dir");
+use crate::app_state::AppState;
+use crate::exit_filter::ExitFilter;
+use crate::location::Location;
+use crate::sort::SortMode;
+
+/// Weight of a single execution depending on its age, the same buckets zoxide uses
+const FRECENCY_WEIGHT: &str = "case \
+    when start_time > strftime('%s', 'now') - 3600 then 4.0 \
+    when start_time > strftime('%s', 'now') - 86400 then 2.0 \
+    when start_time > strftime('%s', 'now') - 604800 then 0.5 \
+    else 0.25 end";
+
+/// Quote a value as sql string literal.
+/// The values are inlined instead of bound as parameters, so the query stays a plain string
+/// that is handed to the reader of every database, and the tests can match the conditions.
+/// Text only ever gets in through this quoting, the other values are integers
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Match the directory and all directories below it, case-sensitively unlike `like`
+fn tree_filter(dir: &str) -> String {
+    let prefix = quote(&format!("{}/", dir.trim_end_matches('/')));
+    format!(
+        "(places.dir == {} or substr(places.dir, 1, length({})) == {})",
+        quote(dir),
+        prefix,
+        prefix
+    )
+}
+
+fn location_conditions(location: &Location, app_state: &AppState) -> Vec<String> {
+    let host = format!("places.host == {}", quote(&app_state.machine));
+    match location {
+        Location::Session => vec![format!("history.session == {}", app_state.session), host],
+        Location::Directory => vec![format!("places.dir == {}", quote(&app_state.dir)), host],
+        Location::DirectoryTree => vec![tree_filter(&app_state.dir), host],
+        // outside of a repository there is no root, so the tree of the current directory is used
+        Location::Repository => vec![
+            tree_filter(app_state.repository.as_ref().unwrap_or(&app_state.dir)),
+            host,
+        ],
+        Location::Machine => vec![host],
+        Location::Everywhere => vec![],
+    }
+}
+
+fn exit_conditions(app_state: &AppState) -> Vec<String> {
+    match app_state.exit_filter {
+        ExitFilter::All => vec![],
+        ExitFilter::Success => vec![String::from("history.exit_status == 0")],
+        ExitFilter::Failed => vec![String::from("history.exit_status != 0")],
+    }
+}
+
+fn time_conditions(app_state: &AppState) -> Vec<String> {
+    let mut conditions = Vec::new();
+    let (since, until) = app_state.time_range.bounds();
+    if let Some(since) = since {
+        conditions.push(format!("history.start_time >= {}", since));
+    }
+    if let Some(until) = until {
+        conditions.push(format!("history.start_time <= {}", until));
+    }
+    conditions
+}
+
+fn where_clause(location: &Location, app_state: &AppState) -> String {
+    let mut conditions = location_conditions(location, app_state);
+    conditions.extend(exit_conditions(app_state));
+    conditions.extend(time_conditions(app_state));
+    if conditions.is_empty() {
+        String::new()
+    } else {
+        format!(" where {}", conditions.join(" and "))
+    }
+}
+
+/// Separator of the hosts and directories lists of grouped entries
+pub const LIST_SEPARATOR: char = '\x1f';
+
+/// The frecency score sums the weights of all executions of a command in the location
+fn frecency_column(grouped: bool, app_state: &AppState) -> String {
+    match app_state.sort {
+        SortMode::Recent => String::from(" null as frecency,"),
+        SortMode::Frecency if grouped => format!(
+            " sum(sum({})) over (partition by history.command_id) as frecency,",
+            FRECENCY_WEIGHT
+        ),
+        SortMode::Frecency => format!(
+            " sum({}) over (partition by history.command_id) as frecency,",
+            FRECENCY_WEIGHT
+        ),
+    }
+}
+
+/// The executions in the location, each with the id of the latest execution of its command in its place
+fn build_executions_query(location: &Location, app_state: &AppState) -> String {
+    let mut query = String::from("select history.command_id as command_id,");
+    query.push_str(" history.place_id as place_id, history.start_time as start_time,");
+    query.push_str(" history.exit_status as exit_status,");
+    query.push_str(" places.host as host, places.dir as dir,");
+    query.push_str(
+        " first_value(history.id) over (partition by history.command_id, history.place_id",
+    );
+    query.push_str(" order by history.start_time desc, history.id desc) as latest_id");
     query.push_str(" from history");
+    query.push_str(" left join places on history.place_id = places.id");
+    query.push_str(&where_clause(location, app_state));
+    query
+}
+
+/// Aggregate the executions per command and place, and sum them up per command.
+/// Only the place the command was run last is kept for every command.
+fn build_runs_query(location: &Location, app_state: &AppState) -> String {
+    let mut query = String::from("select history.command_id as command_id,");
+    query.push_str(" history.place_id as place_id, max(start_time) as start,");
+    query.push_str(" max(history.latest_id) as latest_id,");
+    query.push_str(" min(min(start_time)) over command as first_start,");
+    query.push_str(&frecency_column(true, app_state));
+    query.push_str(" sum(count(*)) over command as count,");
+    query.push_str(" sum(sum(exit_status == 0)) over command * 1.0");
+    query.push_str(" / sum(count(exit_status)) over command as success_rate,");
+    query.push_str(&format!(
+        " group_concat(history.host, char({})) over command as hosts,",
+        LIST_SEPARATOR as u32
+    ));
+    query.push_str(&format!(
+        " group_concat(history.dir, char({})) over command as dirs,",
+        LIST_SEPARATOR as u32
+    ));
+    query.push_str(" row_number() over");
+    query.push_str(
+        " (partition by history.command_id order by max(start_time) desc) as command_rank",
+    );
+    query.push_str(&format!(
+        " from ({}) as history",
+        build_executions_query(location, app_state)
+    ));
+    query.push_str(" group by history.command_id, history.place_id");
+    query.push_str(" window command as (partition by history.command_id)");
+    query
+}
+
+pub fn build_query_string(location: &Location, grouped: bool, app_state: &AppState) -> String {
+    let mut query = String::from("select history.id as id, commands.argv as cmd,");
+    if grouped {
+        query.push_str(" runs.start as start, runs.first_start as first_start,");
+        query.push_str(" exit_status, duration, runs.frecency as frecency,");
+        query.push_str(" runs.count as count, runs.success_rate as success_rate,");
+        query.push_str(" runs.hosts as hosts, runs.dirs as dirs,");
+    } else {
+        query.push_str(" start_time as start, start_time as first_start,");
+        query.push_str(" exit_status, duration,");
+        query.push_str(&frecency_column(false, app_state));
+        query.push_str(" 1 as count, (exit_status == 0) * 1.0 as success_rate,");
+        query.push_str(" places.host as hosts, places.dir as dirs,");
+    }
+    query.push_str(" history.session as session, places.host as host, places.dir as dir");
+    if grouped {
+        query.push_str(&format!(
+            " from ({}) as runs",
+            build_runs_query(location, app_state)
+        ));
+        // the latest execution of the command, in the place it was run last
+        query.push_str(" join history on history.id = runs.latest_id");
+    } else {
+        query.push_str(" from history");
+    }
     query.push_str(" left join commands on history.command_id = commands.id");
     query.push_str(" left join places on history.place_id = places.id");
-    query.push_str(" order by start desc");
-    return query;
+    if grouped {
+        query.push_str(" where runs.command_rank == 1");
+    } else {
+        query.push_str(&where_clause(location, app_state));
+    }
+    match app_state.sort {
+        SortMode::Recent => query.push_str(" order by start desc"),
+        SortMode::Frecency => query.push_str(" order by frecency desc, start desc"),
+    }
+    query
 }
 
 #[cfg(test)]
+#[allow(clippy::module_inception, clippy::useless_vec)]
 mod query {
     use super::*;
+    use crate::time_range::TimeRange;
     use regex::Regex;
 
+    fn app_state() -> AppState {
+        AppState {
+            session: 4711,
+            dir: String::from("/home/someone/skripts"),
+            machine: String::from("testhost"),
+            repository: Some(String::from("/home/someone")),
+            sort: SortMode::Recent,
+            exit_filter: ExitFilter::All,
+            time_range: TimeRange::All,
+            custom_time_range: None,
+        }
+    }
+
     #[test]
     fn has_select_fields() {
         for l in vec![
             Location::Session,
             Location::Directory,
+            Location::DirectoryTree,
+            Location::Repository,
             Location::Machine,
             Location::Everywhere,
         ] {
-            let query = build_query_string(&l, true);
+            let query = build_query_string(&l, true, &app_state());
             assert!(query.contains("history.id as id"));
             assert!(query.contains("exit_status"));
             assert!(query.contains("start"));
@@ -38,11 +225,17 @@ mod query {
     #[test]
     fn contains_host() {
         let re_host = Regex::new(r"host == '.*'").unwrap();
-        for l in vec![Location::Session, Location::Directory, Location::Machine] {
-            let query = build_query_string(&l, true);
+        for l in vec![
+            Location::Session,
+            Location::Directory,
+            Location::DirectoryTree,
+            Location::Repository,
+            Location::Machine,
+        ] {
+            let query = build_query_string(&l, true, &app_state());
             assert!(re_host.is_match(&query));
         }
-        let query = build_query_string(&Location::Everywhere, true);
+        let query = build_query_string(&Location::Everywhere, true, &app_state());
         assert!(!re_host.is_match(&query));
     }
 
@@ -52,10 +245,12 @@ mod query {
         for l in vec![
             Location::Session,
             Location::Directory,
+            Location::DirectoryTree,
+            Location::Repository,
             Location::Machine,
             Location::Everywhere,
         ] {
-            let query = build_query_string(&l, true);
+            let query = build_query_string(&l, true, &app_state());
             assert!(re_group.is_match(&query));
         }
     }
@@ -67,10 +262,12 @@ mod query {
         for l in vec![
             Location::Session,
             Location::Directory,
+            Location::DirectoryTree,
+            Location::Repository,
             Location::Machine,
             Location::Everywhere,
         ] {
-            let query = build_query_string(&l, false);
+            let query = build_query_string(&l, false, &app_state());
             assert!(!re_only_group.is_match(&query));
             assert!(!re_group.is_match(&query));
         }
@@ -78,7 +275,7 @@ mod query {
 
     #[test]
     fn for_session() {
-        let query = build_query_string(&Location::Session, true);
+        let query = build_query_string(&Location::Session, true, &app_state());
         let re_session = Regex::new(r"session == (\d*) and").unwrap();
         let re_host = Regex::new(r"host == '.*'").unwrap();
         let re_group = Regex::new(r"group by history.command_id, history.place_id").unwrap();
@@ -89,8 +286,8 @@ mod query {
 
     #[test]
     fn for_directory() {
-        let query = build_query_string(&Location::Directory, false);
-        let re_directory = Regex::new(r"places.dir like '.*' and").unwrap();
+        let query = build_query_string(&Location::Directory, false, &app_state());
+        let re_directory = Regex::new(r"places.dir == '.*' and").unwrap();
         let re_group = Regex::new(r"group by history.command_id, history.place_id").unwrap();
         assert!(re_directory.is_match(&query));
         assert!(!re_group.is_match(&query));
@@ -98,9 +295,9 @@ mod query {
 
     #[test]
     fn for_machine() {
-        let query = build_query_string(&Location::Machine, true);
+        let query = build_query_string(&Location::Machine, true, &app_state());
         let re_session = Regex::new(r"session == (\d*) and").unwrap();
-        let re_place = Regex::new(r"dir like '.*' and").unwrap();
+        let re_place = Regex::new(r"dir == '.*' and").unwrap();
         let re_host = Regex::new(r"host == '.*'").unwrap();
         let re_group = Regex::new(r"group by history.command_id, history.place_id").unwrap();
         assert!(!re_session.is_match(&query));
@@ -110,9 +307,9 @@ mod query {
     }
     #[test]
     fn for_everywhere() {
-        let query = build_query_string(&Location::Everywhere, true);
+        let query = build_query_string(&Location::Everywhere, true, &app_state());
         let re_session = Regex::new(r"session == (\d*) and").unwrap();
-        let re_place = Regex::new(r"dir like '.*' and").unwrap();
+        let re_place = Regex::new(r"dir == '.*' and").unwrap();
         let re_host = Regex::new(r"host == '.*'").unwrap();
         let re_group = Regex::new(r"group by history.command_id, history.place_id").unwrap();
         assert!(!re_session.is_match(&query));
@@ -120,4 +317,222 @@ mod query {
         assert!(!re_host.is_match(&query));
         assert!(re_group.is_match(&query));
     }
+
+    #[test]
+    fn quotes_values() {
+        let state = AppState {
+            session: 1,
+            dir: String::from("/home/some_one/it's"),
+            machine: String::from("o'host"),
+            repository: None,
+            sort: SortMode::Recent,
+            exit_filter: ExitFilter::All,
+            time_range: TimeRange::All,
+            custom_time_range: None,
+        };
+        let query = build_query_string(&Location::Directory, false, &state);
+        assert!(query.contains("places.host == 'o''host'"));
+        assert!(query.contains("places.dir == '/home/some_one/it''s'"));
+    }
+
+    #[test]
+    fn orders_by_start() {
+        for grouped in [true, false] {
+            let query = build_query_string(&Location::Machine, grouped, &app_state());
+            assert!(query.contains("null as frecency"));
+            assert!(query.ends_with("order by start desc"));
+        }
+        let query = build_query_string(&Location::Machine, false, &app_state());
+        assert!(!query.contains(" over ("));
+    }
+
+    #[test]
+    fn orders_by_frecency() {
+        let state = AppState {
+            sort: SortMode::Frecency,
+            ..app_state()
+        };
+        let re_window =
+            Regex::new(r"sum\(.*\) over \(partition by history.command_id\) as frecency").unwrap();
+        for grouped in [true, false] {
+            let query = build_query_string(&Location::Machine, grouped, &state);
+            assert!(re_window.is_match(&query));
+            assert!(query.ends_with("order by frecency desc, start desc"));
+        }
+        let query = build_query_string(&Location::Machine, true, &state);
+        assert!(query.contains("sum(sum(case"));
+    }
+
+    #[test]
+    fn counts_grouped_runs() {
+        let query = build_query_string(&Location::Directory, true, &app_state());
+        assert!(query.contains("sum(count(*)) over command as count"));
+        assert!(query.contains("min(min(start_time)) over command as first_start"));
+        assert!(query.contains("where runs.command_rank == 1"));
+        let query = build_query_string(&Location::Directory, false, &app_state());
+        assert!(query.contains("1 as count"));
+        assert!(query.contains("start_time as first_start"));
+    }
+
+    #[test]
+    fn groups_runs_in_the_database() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::database::create_schema(&conn).unwrap();
+        conn.execute_batch(
+            "insert into commands (id, argv) values (1, 'make'), (2, 'ls');
+            insert into places (id, host, dir) values (1, 'h1', '/a'), (2, 'h2', '/b');
+            insert into history (id, session, command_id, place_id, exit_status, start_time)
+            values (1, 1, 1, 1, 0, 100), (2, 1, 1, 2, 1, 200), (3, 1, 1, 1, 0, 150),
+                (4, 2, 1, 2, 0, 200), (5, 1, 2, 1, 0, 50);",
+        )
+        .unwrap();
+        // the order of the lists is up to sqlite
+        let sorted = |list: String| {
+            let mut values: Vec<&str> = list.split(LIST_SEPARATOR).collect();
+            values.sort();
+            values.join(&LIST_SEPARATOR.to_string())
+        };
+        let query = build_query_string(&Location::Everywhere, true, &app_state());
+        let mut stmt = conn.prepare(&query).unwrap();
+        let rows: Vec<(i64, String, i64, i64, i64, String, String)> = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get("id")?,
+                    row.get("cmd")?,
+                    row.get("count")?,
+                    row.get("start")?,
+                    row.get("first_start")?,
+                    sorted(row.get("hosts")?),
+                    sorted(row.get("dirs")?),
+                ))
+            })
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect();
+        let separator = LIST_SEPARATOR.to_string();
+        let list = |values: &[&str]| values.join(&separator);
+        assert_eq!(
+            rows,
+            vec![
+                (
+                    4,
+                    String::from("make"),
+                    4,
+                    200,
+                    100,
+                    list(&["h1", "h2"]),
+                    list(&["/a", "/b"])
+                ),
+                (
+                    5,
+                    String::from("ls"),
+                    1,
+                    50,
+                    50,
+                    list(&["h1"]),
+                    list(&["/a"])
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn for_directory_tree() {
+        let query = build_query_string(&Location::DirectoryTree, true, &app_state());
+        assert!(query.contains(
+            "(places.dir == '/home/someone/skripts' or substr(places.dir, 1, length('/home/someone/skripts/')) == '/home/someone/skripts/') and places.host == 'testhost'"
+        ));
+        let state = AppState {
+            dir: String::from("/"),
+            ..app_state()
+        };
+        let query = build_query_string(&Location::DirectoryTree, true, &state);
+        assert!(query.contains("substr(places.dir, 1, length('/')) == '/'"));
+    }
+
+    #[test]
+    fn matches_directories_case_sensitively() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::database::create_schema(&conn).unwrap();
+        conn.execute_batch(
+            "insert into commands (id, argv) values (1, 'make');
+            insert into places (id, host, dir) values
+                (1, 'testhost', '/home/someone/skripts'), (2, 'testhost', '/home/someone/Skripts'),
+                (3, 'testhost', '/home/someone/skripts/sub'), (4, 'testhost', '/home/someone/SKRIPTS/sub'),
+                (5, 'testhost', '/home/someone/skripts2');
+            insert into history (session, command_id, place_id, start_time)
+            values (1, 1, 1, 1), (1, 1, 2, 2), (1, 1, 3, 3), (1, 1, 4, 4), (1, 1, 5, 5);",
+        )
+        .unwrap();
+        let dirs = |location: Location| -> Vec<String> {
+            let query = build_query_string(&location, false, &app_state());
+            let mut stmt = conn.prepare(&query).unwrap();
+            let rows = stmt.query_map([], |row| row.get("dir")).unwrap();
+            rows.map(|dir| dir.unwrap()).collect()
+        };
+        assert_eq!(dirs(Location::Directory), vec!["/home/someone/skripts"]);
+        assert_eq!(
+            dirs(Location::DirectoryTree),
+            vec!["/home/someone/skripts/sub", "/home/someone/skripts"]
+        );
+    }
+
+    #[test]
+    fn for_repository() {
+        let query = build_query_string(&Location::Repository, false, &app_state());
+        assert!(query.contains(
+            "(places.dir == '/home/someone' or substr(places.dir, 1, length('/home/someone/')) == '/home/someone/') and places.host == 'testhost'"
+        ));
+        let state = AppState {
+            repository: None,
+            ..app_state()
+        };
+        let query = build_query_string(&Location::Repository, false, &state);
+        assert!(query.contains("length('/home/someone/skripts/')"));
+    }
+
+    #[test]
+    fn filters_exit_status() {
+        let query = build_query_string(&Location::Everywhere, false, &app_state());
+        assert!(!query.contains("history.exit_status =="));
+        assert!(!query.contains("history.exit_status !="));
+        let state = AppState {
+            exit_filter: ExitFilter::Success,
+            ..app_state()
+        };
+        let query = build_query_string(&Location::Everywhere, false, &state);
+        assert!(query.contains(" where history.exit_status == 0"));
+        let state = AppState {
+            exit_filter: ExitFilter::Failed,
+            ..app_state()
+        };
+        let query = build_query_string(&Location::Machine, true, &state);
+        assert!(query.contains("places.host == 'testhost' and history.exit_status != 0"));
+    }
+
+    #[test]
+    fn filters_time_range() {
+        let re_time = Regex::new(r"start_time [<>]= \d+").unwrap();
+        let query = build_query_string(&Location::Session, true, &app_state());
+        assert!(!re_time.is_match(&query));
+        let state = AppState {
+            time_range: TimeRange::Week,
+            ..app_state()
+        };
+        let query = build_query_string(&Location::Session, true, &state);
+        let re_since =
+            Regex::new(r"places.host == 'testhost' and history.start_time >= \d+").unwrap();
+        assert!(re_since.is_match(&query));
+        let state = AppState {
+            time_range: TimeRange::Custom {
+                since: Some(1640995200),
+                until: Some(1641081600),
+            },
+            ..app_state()
+        };
+        let query = build_query_string(&Location::Everywhere, false, &state);
+        assert!(query.contains(
+            " where history.start_time >= 1640995200 and history.start_time <= 1641081600"
+        ));
+    }
 }
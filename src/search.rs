@@ -0,0 +1,113 @@
+use crate::app_state::AppState;
+use crate::database::read_history;
+use crate::history::History;
+use crate::location::Location;
+use crate::query::build_query_string;
+use clap::ValueEnum;
+use std::io::{self, ErrorKind, Write};
+
+/// How the entries found by `search` are printed
+#[derive(PartialEq, Eq, Debug, ValueEnum, Copy, Clone, Default)]
+pub enum OutputFormat {
+    /// Only the command
+    #[default]
+    Plain,
+    /// Start time, exit status, duration, count, host, directory and command separated by tabs
+    Tsv,
+    /// One JSON object per line
+    Json,
+}
+
+/// Print the entries of the location matching all words of the query
+pub fn search(
+    location: &Location,
+    grouped: bool,
+    app_state: &AppState,
+    query: &str,
+    limit: Option<usize>,
+    format: OutputFormat,
+) -> Result<(), String> {
+    let entries = read_history(
+        &build_query_string(location, grouped, app_state),
+        app_state.sort,
+    )
+    .map_err(|e| format!("Could not read database: {}", e))?
+    .filter(|entry| match entry {
+        Ok(entry) => matches(&entry.cmd, query),
+        Err(_) => true,
+    })
+    .take(limit.unwrap_or(usize::MAX));
+
+    let mut out = io::stdout().lock();
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Could not read entry: {}", e))?;
+        let line = match format {
+            OutputFormat::Plain => entry.cmd.clone(),
+            OutputFormat::Tsv => tsv_line(&entry),
+            OutputFormat::Json => serde_json::to_string(&entry).map_err(|e| e.to_string())?,
+        };
+        match writeln!(out, "{}", line) {
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => return Ok(()),
+            result => result.map_err(|e| e.to_string())?,
+        }
+    }
+    Ok(())
+}
+
+/// Whether the command contains every word of the query, ignoring case unless a word has uppercase letters
+fn matches(cmd: &str, query: &str) -> bool {
+    query.split_whitespace().all(|word| {
+        if word.chars().any(char::is_uppercase) {
+            cmd.contains(word)
+        } else {
+            cmd.to_lowercase().contains(word)
+        }
+    })
+}
+
+fn tsv_line(entry: &History) -> String {
+    let optional = |value: Option<i64>| value.map(|v| v.to_string()).unwrap_or_default();
+    [
+        entry.start.to_string(),
+        optional(entry.exit_status),
+        optional(entry.duration),
+        entry.count.to_string(),
+        tsv_escape(&entry.host),
+        tsv_escape(&entry.dir),
+        tsv_escape(&entry.cmd),
+    ]
+    .join("\t")
+}
+
+/// Escape the characters that would break the columns or lines
+fn tsv_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_all_words() {
+        assert!(matches("git commit -m fix", "git fix"));
+        assert!(!matches("git commit -m fix", "git push"));
+        assert!(matches("anything", ""));
+    }
+
+    #[test]
+    fn matches_smart_case() {
+        assert!(matches("make Release", "release"));
+        assert!(matches("make Release", "Release"));
+        assert!(!matches("make release", "Release"));
+    }
+
+    #[test]
+    fn escapes_tsv() {
+        assert_eq!(tsv_escape("a\tb\nc\\d"), "a\\tb\\nc\\\\d");
+    }
+}
@@ -0,0 +1,13 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// Order of the history entries handed to skim
+#[derive(PartialEq, Eq, Hash, Debug, ValueEnum, Deserialize, Copy, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum SortMode {
+    /// Most recent commands first
+    #[default]
+    Recent,
+    /// Commands run often and recently first
+    Frecency,
+}
@@ -0,0 +1,151 @@
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone};
+use humantime::parse_duration;
+
+/// The time window of the shown history entries
+#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone, Default)]
+pub enum TimeRange {
+    #[default]
+    All,
+    Today,
+    Week,
+    Month,
+    /// Given on the command line, as unix timestamps
+    Custom {
+        since: Option<i64>,
+        until: Option<i64>,
+    },
+}
+
+impl TimeRange {
+    /// The first and last start time of the range, if limited
+    pub fn bounds(&self) -> (Option<i64>, Option<i64>) {
+        let now = Local::now();
+        match self {
+            TimeRange::All => (None, None),
+            TimeRange::Today => {
+                let midnight = now.date_naive().and_hms_opt(0, 0, 0).unwrap();
+                let midnight = Local.from_local_datetime(&midnight).earliest();
+                (midnight.map(|m| m.timestamp()), None)
+            }
+            TimeRange::Week => (Some(now.timestamp() - 7 * 86400), None),
+            TimeRange::Month => (Some(now.timestamp() - 30 * 86400), None),
+            TimeRange::Custom { since, until } => (*since, *until),
+        }
+    }
+
+    /// The next range of the cycle, which starts with the custom range if there is one
+    pub fn next(self, custom: Option<TimeRange>) -> TimeRange {
+        match self {
+            TimeRange::All => custom.unwrap_or(TimeRange::Today),
+            TimeRange::Custom { .. } => TimeRange::Today,
+            TimeRange::Today => TimeRange::Week,
+            TimeRange::Week => TimeRange::Month,
+            TimeRange::Month => TimeRange::All,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let format_time = |timestamp: i64| {
+            Local
+                .timestamp_opt(timestamp, 0)
+                .single()
+                .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default()
+        };
+        match self {
+            TimeRange::All => String::from("all"),
+            TimeRange::Today => String::from("today"),
+            TimeRange::Week => String::from("7 days"),
+            TimeRange::Month => String::from("30 days"),
+            TimeRange::Custom { since, until } => {
+                let mut range = Vec::new();
+                if let Some(since) = since {
+                    range.push(format!("since {}", format_time(*since)));
+                }
+                if let Some(until) = until {
+                    range.push(format!("until {}", format_time(*until)));
+                }
+                range.join(" ")
+            }
+        }
+    }
+}
+
+/// Parse a time given either as duration before now ("2h", "3days")
+/// or as local date ("2024-01-31", "2024-01-31 12:00") into a unix timestamp
+pub fn parse_time(value: &str) -> Result<i64, String> {
+    if let Ok(duration) = parse_duration(value) {
+        return Ok(Local::now().timestamp() - duration.as_secs() as i64);
+    }
+    let datetime = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .ok_or(format!(
+            "'{}' is neither a duration like '2h' or '3days' nor a date like '2024-01-31 12:00'",
+            value
+        ))?;
+    Local
+        .from_local_datetime(&datetime)
+        .earliest()
+        .map(|t| t.timestamp())
+        .ok_or(format!("'{}' does not exist in the local time zone", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        let now = Local::now().timestamp();
+        let two_hours_ago = parse_time("2h").unwrap();
+        assert!((now - 7200 - two_hours_ago).abs() <= 1);
+        let three_days_ago = parse_time("3days").unwrap();
+        assert!((now - 3 * 86400 - three_days_ago).abs() <= 1);
+    }
+
+    #[test]
+    fn parses_dates() {
+        let date = Local.with_ymd_and_hms(2024, 1, 31, 0, 0, 0).unwrap();
+        assert_eq!(parse_time("2024-01-31").unwrap(), date.timestamp());
+        let time = Local.with_ymd_and_hms(2024, 1, 31, 12, 30, 0).unwrap();
+        assert_eq!(parse_time("2024-01-31 12:30").unwrap(), time.timestamp());
+    }
+
+    #[test]
+    fn cycles_through_ranges() {
+        let custom = TimeRange::Custom {
+            since: Some(100),
+            until: None,
+        };
+        let mut range = custom;
+        let mut cycle = Vec::new();
+        for _ in 0..6 {
+            range = range.next(Some(custom));
+            cycle.push(range);
+        }
+        assert_eq!(
+            cycle,
+            vec![
+                TimeRange::Today,
+                TimeRange::Week,
+                TimeRange::Month,
+                TimeRange::All,
+                custom,
+                TimeRange::Today
+            ]
+        );
+        assert_eq!(TimeRange::All.next(None), TimeRange::Today);
+        assert_eq!(TimeRange::Today.next(None), TimeRange::Week);
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(parse_time("yesterday-ish").is_err());
+    }
+}
@@ -1,12 +1,38 @@
+use crate::action::Action;
+use crate::app_state::AppState;
+use crate::config::config;
 use crate::environment::*;
+use crate::exit_filter::ExitFilter;
 use crate::location::Location;
+use crate::sort::SortMode;
 use enum_map::enum_map;
+use std::path::Path;
 
-pub fn generate_title(location: &Location) -> String {
+/// The location tabs in the order they are shown
+const TABS: [(Location, &str); 6] = [
+    (Location::Session, "Session"),
+    (Location::Directory, "Directory"),
+    (Location::DirectoryTree, "Tree"),
+    (Location::Repository, "Repo"),
+    (Location::Machine, "Host"),
+    (Location::Everywhere, "Everywhere"),
+];
+
+#[allow(
+    clippy::needless_return,
+    clippy::redundant_closure_call,
+    clippy::needless_borrow,
+    clippy::clone_on_copy
+)]
+pub fn generate_title(location: &Location, app_state: &AppState) -> String {
     let extra_info = |theloc: &Location| -> String {
         return match theloc {
             Location::Session => get_current_session_id(),
-            Location::Directory => get_current_dir(),
+            Location::Directory | Location::DirectoryTree => get_current_dir(),
+            Location::Repository => match &app_state.repository {
+                Some(root) => format!("{} ({})", repository_name(root), root),
+                None => format!("none, showing {}", get_current_dir()),
+            },
             Location::Machine => get_current_host(),
             _ => String::from(""),
         };
@@ -15,36 +41,154 @@ pub fn generate_title(location: &Location) -> String {
     let location_map = enum_map! {
         Location::Session => "Session:",
         Location::Directory => "Directory:",
+        Location::DirectoryTree => "Directory tree:",
+        Location::Repository => "Repository:",
         Location::Machine => "Host:",
         Location::Everywhere => "Everywhere",
     };
 
-    let header_map = enum_map! {
-        Location::Session =>
-" ┏━━━━━━━━━━━┱─────────────┬────────┬──────────────┐
- ┃F1: Session┃F2: Directory│F3: Host│F4: Everywhere│ F5: Toggle group
-━┛           ┗━━━━━━━━━━━━━┷━━━━━━━━┷━━━━━━━━━━━━━━┷━━━━━━━━━━━━━━━━━",
-        Location::Directory =>
-" ┌───────────┲━━━━━━━━━━━━━┱────────┬──────────────┐
- │F1: Session┃F2: Directory┃F3: Host│F4: Everywhere│ F5: Toggle group
-━┷━━━━━━━━━━━┛             ┗━━━━━━━━┷━━━━━━━━━━━━━━┷━━━━━━━━━━━━━━━━━",
-
-        Location::Machine =>
-" ┌───────────┬─────────────┲━━━━━━━━┱──────────────┐
- │F1: Session│F2: Directory┃F3: Host┃F4: Everywhere│ F5: Toggle group
-━┷━━━━━━━━━━━┷━━━━━━━━━━━━━┛        ┗━━━━━━━━━━━━━━┷━━━━━━━━━━━━━━━━━",
-
-        Location::Everywhere =>
-" ┌───────────┬─────────────┬────────┲━━━━━━━━━━━━━━┓
- │F1: Session│F2: Directory│F3: Host┃F4: Everywhere┃ F5: Toggle group
-━┷━━━━━━━━━━━┷━━━━━━━━━━━━━┷━━━━━━━━┛              ┗━━━━━━━━━━━━━━━━━",
+    let sort_info = match app_state.sort {
+        SortMode::Recent => "recent",
+        SortMode::Frecency => "frecency",
+    };
+    let exit_info = match app_state.exit_filter {
+        ExitFilter::All => "all",
+        ExitFilter::Success => "success",
+        ExitFilter::Failed => "failed",
     };
+    let keys = &config().keys;
+    let status = format!(
+        " │ Sort: {} ({}) │ Exit: {} ({})",
+        sort_info,
+        key_label(keys.key(Action::ToggleSort)),
+        exit_info,
+        key_label(keys.key(Action::CycleExitStatus))
+    );
+    let tabs: Vec<(Location, String)> = TABS
+        .iter()
+        .map(|(location, name)| {
+            let binding = keys.key(Action::for_location(*location));
+            (*location, format!("{}: {}", key_label(binding), name))
+        })
+        .collect();
 
     let title = format!(
-        "{} {}\n{}\n",
+        "{} {}{}\n{}\n",
         &location_map[location.clone()],
         &extra_info,
-        &header_map[location.clone()],
+        &status,
+        &draw_tabs(
+            &tabs,
+            location,
+            &format!(
+                " {}: Toggle group │ {}: {}",
+                key_label(keys.key(Action::ToggleGroup)),
+                key_label(keys.key(Action::CycleTimeRange)),
+                app_state.time_range.describe()
+            )
+        ),
     );
     return title.to_string();
 }
+
+fn repository_name(root: &str) -> String {
+    Path::new(root)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or(root.to_string())
+}
+
+/// Short label of a key binding, e.g. `^S` for `ctrl-s`
+fn key_label(binding: &str) -> String {
+    match binding.strip_prefix("ctrl-") {
+        Some(key) if key.chars().count() == 1 => format!("^{}", key.to_uppercase()),
+        _ if binding.len() > 1 && binding.starts_with('f') => binding.to_uppercase(),
+        _ => binding.to_string(),
+    }
+}
+
+/// Draw the tabs as box, with the selected tab opened towards the list
+fn draw_tabs<T: AsRef<str>>(tabs: &[(Location, T)], selected: &Location, trailer: &str) -> String {
+    let mut top = String::from(" ");
+    let mut middle = String::from(" ");
+    let mut bottom = String::from("━");
+    let mut previous = false;
+
+    for (i, (location, label)) in tabs.iter().enumerate() {
+        let current = location == selected;
+        let label = label.as_ref();
+        let width = label.chars().count();
+        let (top_edge, bottom_edge) = match (i == 0, previous, current) {
+            (true, _, true) => ('┏', '┛'),
+            (true, _, false) => ('┌', '┷'),
+            (false, true, _) => ('┱', '┗'),
+            (false, false, true) => ('┲', '┛'),
+            (false, false, false) => ('┬', '┷'),
+        };
+        top.push(top_edge);
+        middle.push(if current || previous { '┃' } else { '│' });
+        bottom.push(bottom_edge);
+
+        top.push_str(&(if current { "━" } else { "─" }).repeat(width));
+        middle.push_str(label);
+        bottom.push_str(&(if current { " " } else { "━" }).repeat(width));
+        previous = current;
+    }
+
+    top.push(if previous { '┓' } else { '┐' });
+    middle.push(if previous { '┃' } else { '│' });
+    bottom.push(if previous { '┗' } else { '┷' });
+    middle.push_str(trailer);
+    bottom.push_str(&"━".repeat(trailer.chars().count()));
+
+    format!("{}\n{}\n{}", top, middle, bottom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TABS: [(Location, &str); 4] = [
+        (Location::Session, "F1: Session"),
+        (Location::Directory, "F2: Directory"),
+        (Location::Machine, "F3: Host"),
+        (Location::Everywhere, "F4: Everywhere"),
+    ];
+
+    #[test]
+    fn labels_keys() {
+        assert_eq!(key_label("f1"), "F1");
+        assert_eq!(key_label("ctrl-s"), "^S");
+        assert_eq!(key_label("alt-s"), "alt-s");
+    }
+
+    #[test]
+    fn draws_first_tab() {
+        assert_eq!(
+            draw_tabs(&TABS, &Location::Session, " F5: Toggle group"),
+            " ┏━━━━━━━━━━━┱─────────────┬────────┬──────────────┐
+ ┃F1: Session┃F2: Directory│F3: Host│F4: Everywhere│ F5: Toggle group
+━┛           ┗━━━━━━━━━━━━━┷━━━━━━━━┷━━━━━━━━━━━━━━┷━━━━━━━━━━━━━━━━━"
+        );
+    }
+
+    #[test]
+    fn draws_middle_tab() {
+        assert_eq!(
+            draw_tabs(&TABS, &Location::Machine, " F5: Toggle group"),
+            " ┌───────────┬─────────────┲━━━━━━━━┱──────────────┐
+ │F1: Session│F2: Directory┃F3: Host┃F4: Everywhere│ F5: Toggle group
+━┷━━━━━━━━━━━┷━━━━━━━━━━━━━┛        ┗━━━━━━━━━━━━━━┷━━━━━━━━━━━━━━━━━"
+        );
+    }
+
+    #[test]
+    fn draws_last_tab() {
+        assert_eq!(
+            draw_tabs(&TABS, &Location::Everywhere, " F5: Toggle group"),
+            " ┌───────────┬─────────────┬────────┲━━━━━━━━━━━━━━┓
+ │F1: Session│F2: Directory│F3: Host┃F4: Everywhere┃ F5: Toggle group
+━┷━━━━━━━━━━━┷━━━━━━━━━━━━━┷━━━━━━━━┛              ┗━━━━━━━━━━━━━━━━━"
+        );
+    }
+}